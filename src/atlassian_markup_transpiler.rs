@@ -270,12 +270,26 @@ fn build_admotion_parser<'a>(
         })
 }
 
+/// Any line that is not recognised as markup is kept as plain text.
+fn build_plain_text_line_parser<'a>() -> impl Parser<'a, &'a str, MarkUpNode<'a>> {
+    let terminated_line = none_of("\n").repeated().to_slice().then_ignore(just("\n"));
+    let last_line = none_of("\n")
+        .repeated()
+        .at_least(1)
+        .to_slice()
+        .then_ignore(end());
+    terminated_line.or(last_line).map(MarkUpNode::PlainText)
+}
+
 fn build_atlassian_markup_parser<'a>() -> impl Parser<'a, &'a str, Vec<MarkUpNode<'a>>> {
     let heading = build_atlassian_markup_heading_parser();
-    heading.repeated().collect()
+    let plain_text = build_plain_text_line_parser();
+    heading.or(plain_text).repeated().collect()
 }
 
 pub fn transpile_atlassian_markup_to_markdown(atlassian_markup: &str) -> String {
+    let atlassian_markup = atlassian_markup.replace("\r\n", "\n");
+    let atlassian_markup = atlassian_markup.as_str();
     let atlassian_markup_ast = build_atlassian_markup_parser()
         .parse(atlassian_markup)
         .unwrap();
@@ -344,6 +358,17 @@ mod tests {
         assert_eq!(parser, md_heading_line);
     }
 
+    #[parameterized(
+            single_line = {"Some text", "Some text\n"},
+            heading_then_text = {"h1. Title\nSome text\n", "# Title\nSome text\n"},
+            windows_line_endings = {"h2. Title\r\nSome text", "## Title\nSome text\n"},
+            blank_lines = {"First\n\nSecond\n", "First\n\nSecond\n"},
+        )]
+    fn keeps_plain_text_lines(am_text: &str, md_text: &str) {
+        let parser = transpile_atlassian_markup_to_markdown(am_text);
+        assert_eq!(parser, md_text);
+    }
+
     #[test]
     fn parse_codeblock_all_params() {
         let markup = "{code:title=This is my title|linenumbers=true|language=python|firstline=0001|collapse=true}
@@ -1,38 +1,601 @@
 use etcetera::{self, BaseStrategy};
-use serde::Deserialize;
-use std::{fs, io};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::PathBuf,
+    time::Duration,
+};
 use thiserror::Error;
 
+use crate::ticket_template::{IconSet, TicketTemplate};
+
 #[derive(Deserialize)]
 pub struct Config {
     pub jira: JiraConfig,
+    #[serde(default)]
+    pub diagnostics: DiagnosticsConfig,
+    #[serde(default)]
+    pub refrence_display: RefrenceDisplay,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub templates: TemplatesConfig,
+    #[serde(default)]
+    pub hover: HoverConfig,
 }
 
-#[derive(Deserialize)]
+/// The most recent `comment_count` comments are fetched the first time a ticket is
+/// hovered, and shown through the `{comments}` placeholder. Set it to 0 to never fetch
+/// them.
+///
+/// ```toml
+/// [hover]
+/// comment_count = 3
+/// ```
+#[derive(Deserialize, Clone, Copy)]
+pub struct HoverConfig {
+    #[serde(default = "default_comment_count")]
+    pub comment_count: usize,
+}
+
+impl Default for HoverConfig {
+    fn default() -> Self {
+        HoverConfig {
+            comment_count: default_comment_count(),
+        }
+    }
+}
+
+fn default_comment_count() -> usize {
+    3
+}
+
+/// How tickets are shown, see `TicketTemplate` for what a template can contain. A
+/// template that can not be parsed fails loading the config.
+///
+/// ```toml
+/// [templates]
+/// icons = "ascii"
+/// inlay_hint = ": {title} [{status}]{?due} due {due}{/due}"
+/// tooltip = "{issue_type|Ticket} reported by {reporter|nobody}"
+/// ```
+#[derive(Deserialize, Clone)]
+pub struct TemplatesConfig {
+    #[serde(default)]
+    pub icons: IconSet,
+    #[serde(default = "default_hover_template")]
+    pub hover: TicketTemplate,
+    #[serde(default = "default_inlay_hint_template")]
+    pub inlay_hint: TicketTemplate,
+    /// Inlay hints get no tooltip when this comes out empty.
+    #[serde(default = "default_tooltip_template")]
+    pub tooltip: TicketTemplate,
+}
+
+impl Default for TemplatesConfig {
+    fn default() -> Self {
+        TemplatesConfig {
+            icons: IconSet::default(),
+            hover: default_hover_template(),
+            inlay_hint: default_inlay_hint_template(),
+            tooltip: default_tooltip_template(),
+        }
+    }
+}
+
+fn default_hover_template() -> TicketTemplate {
+    TicketTemplate::parse(
+        r"
+# {title}
+---
+{icon:status} {status} | {icon:date} {updated|Never updated} | {icon:assignee} {assignee|Unassigned}
+
+{details}
+{?progress}
+**Progress:** {progress}
+{/progress}{?related}
+{related}{/related}
+---
+{description}
+{?sections}
+{sections}{/sections}{?comments}
+---
+### Comments
+
+{comments}{/comments}
+{?stale}
+*Jira could not be reached, this is how the ticket was {stale} ago.*
+{/stale}",
+    )
+    .unwrap()
+}
+
+fn default_inlay_hint_template() -> TicketTemplate {
+    TicketTemplate::parse(": {title} ({status}, {assignee|Unassigned}{?stale}, stale{/stale})")
+        .unwrap()
+}
+
+fn default_tooltip_template() -> TicketTemplate {
+    TicketTemplate::parse(
+        "{?stale}Jira could not be reached, this is how {key} was {stale} ago{/stale}",
+    )
+    .unwrap()
+}
+
+/// Tickets are kept on disk between editor sessions, and refetched once older than
+/// `ttl_minutes`. Older tickets are still shown, marked as stale, while Jira can not be
+/// reached. Every `sync_interval_minutes` the tickets that changed are fetched in the
+/// background, which can be turned off by setting it to 0.
+///
+/// ```toml
+/// [cache]
+/// ttl_minutes = 60
+/// sync_interval_minutes = 5
+/// ```
+#[derive(Deserialize, Clone, Copy)]
+pub struct CacheConfig {
+    #[serde(default = "default_cache_ttl_minutes")]
+    pub ttl_minutes: u64,
+    #[serde(default = "default_sync_interval_minutes")]
+    pub sync_interval_minutes: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            ttl_minutes: default_cache_ttl_minutes(),
+            sync_interval_minutes: default_sync_interval_minutes(),
+        }
+    }
+}
+
+fn default_cache_ttl_minutes() -> u64 {
+    60
+}
+
+fn default_sync_interval_minutes() -> u64 {
+    5
+}
+
+impl CacheConfig {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_minutes * 60)
+    }
+
+    pub fn sync_interval(&self) -> Option<Duration> {
+        (self.sync_interval_minutes > 0)
+            .then(|| Duration::from_secs(self.sync_interval_minutes * 60))
+    }
+}
+
+/// How hard Jira is asked. Requests are spread out to at most `requests_per_second`,
+/// given up on after `request_timeout_seconds`, and retried up to `max_retries` times
+/// when Jira is slow, unreachable or rate limiting.
+///
+/// ```toml
+/// [network]
+/// requests_per_second = 5.0
+/// request_timeout_seconds = 30
+/// max_retries = 3
+/// ```
+#[derive(Deserialize, Clone, Copy)]
+pub struct NetworkConfig {
+    #[serde(default = "default_requests_per_second")]
+    pub requests_per_second: f64,
+    #[serde(default = "default_request_timeout_seconds")]
+    pub request_timeout_seconds: u64,
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            requests_per_second: default_requests_per_second(),
+            request_timeout_seconds: default_request_timeout_seconds(),
+            max_retries: default_max_retries(),
+        }
+    }
+}
+
+fn default_requests_per_second() -> f64 {
+    5.0
+}
+
+fn default_request_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+impl NetworkConfig {
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout_seconds)
+    }
+}
+
+/// Where the details of each ticket refrence are shown: after it as an inlay hint,
+/// or on the line above it as a code lens, which is quieter on dense lines.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RefrenceDisplay {
+    #[default]
+    InlayHints,
+    CodeLens,
+    Both,
+}
+
+impl RefrenceDisplay {
+    pub fn shows_inlay_hints(self) -> bool {
+        self != RefrenceDisplay::CodeLens
+    }
+
+    pub fn shows_code_lens(self) -> bool {
+        self != RefrenceDisplay::InlayHints
+    }
+}
+
+/// ```toml
+/// [jira]
+/// host = "https://example.atlassian.net"
+/// email = "me@example.com"
+/// api_token = "..."
+/// projects = ["AUTO", "OPS"]
+/// prefetch_jql = "assignee = currentUser() AND resolution = Unresolved"
+/// other_projects = "ignore"
+///
+/// [jira.custom_fields]
+/// customfield_10016 = { name = "Story points", type = "number" }
+/// customfield_10020 = { name = "Sprint", type = "option_list" }
+/// customfield_10042 = { name = "Acceptance criteria", type = "markup" }
+/// ```
+#[derive(Deserialize, Clone)]
 pub struct JiraConfig {
     pub host: String,
     pub email: String,
     pub api_token: String,
+    /// Keys of the projects refrences are looked up in. Every project when left empty.
+    #[serde(default)]
+    pub projects: Vec<String>,
+    /// Tickets fetched on start up on top of the refrenced ones, so that they can be
+    /// searched for and completed straight away. Limited to `projects`.
+    #[serde(default)]
+    pub prefetch_jql: Option<String>,
+    #[serde(default)]
+    pub other_projects: OtherProjects,
+    /// Keyed by the ID of the field, as in `customfield_10016`.
+    #[serde(default)]
+    pub custom_fields: BTreeMap<String, CustomFieldConfig>,
+}
+
+/// A custom field read from tickets, shown under `name` in the details of a hover and
+/// through the `{custom:name}` placeholder.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct CustomFieldConfig {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: CustomFieldType,
+}
+
+/// How the value of a custom field is read and shown.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CustomFieldType {
+    Number,
+    Text,
+    /// Atlassian markup, shown as a section of its own in hovers.
+    Markup,
+    /// Shown by the user's display name.
+    User,
+    /// One or more options of a select list, or the likes of sprints.
+    OptionList,
+}
+
+/// What happens to refrences to tickets of projects not in `projects`. Text such as
+/// `SHA-256` looks like a ticket key, so these can be ignored entirely.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OtherProjects {
+    /// Looked up when a document refrencing them is shown, but not on start up.
+    #[default]
+    FetchOnDemand,
+    Ignore,
+}
+
+impl JiraConfig {
+    pub fn is_configured_project(&self, ticket_key: &str) -> bool {
+        let project = ticket_key
+            .split_once('-')
+            .map_or(ticket_key, |(project, _)| project);
+        self.projects.is_empty() || self.projects.iter().any(|x| x == project)
+    }
+
+    /// Unlike `is_configured_project`, false for every key when `projects` is left empty.
+    pub fn lists_project(&self, ticket_key: &str) -> bool {
+        !self.projects.is_empty() && self.is_configured_project(ticket_key)
+    }
+
+    /// Jira is reached at `host`, so it has to be a full URL such as
+    /// `https://example.atlassian.net` rather than just the domain.
+    fn check_host(&self) -> Result<(), ConfigError> {
+        match Url::parse(&self.host) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+            _ => Err(ConfigError::InvalidHost(self.host.to_owned())),
+        }
+    }
+}
+
+/// Which ticket refrences are reported as diagnostics, and how severely.
+///
+/// ```toml
+/// [diagnostics]
+/// missing_ticket = "error"
+/// status_categories = { done = "warning" }
+/// statuses = { "Won't Do" = "error", "In Review" = "hint" }
+/// ```
+#[derive(Deserialize, Clone)]
+pub struct DiagnosticsConfig {
+    /// Keyed by Jira's status category: `new`, `indeterminate` or `done`.
+    #[serde(default = "default_status_category_severities")]
+    pub status_categories: HashMap<String, DiagnosticLevel>,
+    /// Keyed by status name. Takes precedence over the status category.
+    #[serde(default)]
+    pub statuses: HashMap<String, DiagnosticLevel>,
+    /// For tickets Jira reports as not existing or not visible to the user. Only keys of
+    /// projects listed in `jira.projects` are flagged, as the likes of `SHA-256` look like
+    /// keys too.
+    #[serde(default = "default_missing_ticket_severity")]
+    pub missing_ticket: DiagnosticLevel,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticLevel {
+    Off,
+    Hint,
+    Information,
+    Warning,
+    Error,
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        DiagnosticsConfig {
+            status_categories: default_status_category_severities(),
+            statuses: HashMap::new(),
+            missing_ticket: default_missing_ticket_severity(),
+        }
+    }
+}
+
+fn default_status_category_severities() -> HashMap<String, DiagnosticLevel> {
+    HashMap::from([(String::from("done"), DiagnosticLevel::Warning)])
+}
+
+fn default_missing_ticket_severity() -> DiagnosticLevel {
+    DiagnosticLevel::Hint
+}
+
+impl DiagnosticsConfig {
+    /// The status name is matched ignoring case before falling back to the category.
+    pub fn severity_for_status(&self, status: &str, status_category: &str) -> DiagnosticLevel {
+        self.statuses
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(status))
+            .or_else(|| self.status_categories.get_key_value(status_category))
+            .map_or(DiagnosticLevel::Off, |(_, level)| *level)
+    }
+}
+
+/// Where the tickets fetched from the Jira at `host` are cached between sessions.
+pub fn ticket_cache_file(host: &str) -> Option<PathBuf> {
+    let mut cache_file = etcetera::choose_base_strategy().ok()?.cache_dir();
+    let file_name: String = host
+        .chars()
+        .map(|character| {
+            if character.is_ascii_alphanumeric() {
+                character
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    cache_file.push(format!("refrences-lsp/tickets/{file_name}.json"));
+    Some(cache_file)
 }
 
 impl Config {
     pub fn from_file() -> Result<Config, ConfigError> {
         let mut config_file = etcetera::choose_base_strategy()
             .map(|x| x.config_dir())
-            .unwrap();
+            .map_err(|_| ConfigError::NoConfigDirectory)?;
         config_file.push("refrences-lsp/config.toml");
         let file_contents = fs::read_to_string(config_file)?;
-        let config: Config = toml::from_str(&file_contents).unwrap();
+        let config: Config = toml::from_str(&file_contents)?;
+        config.jira.check_host()?;
         Ok(config)
     }
 }
 
 #[derive(Error, Debug)]
 pub enum ConfigError {
-    #[error("Error reading file")]
+    #[error("Error reading file: {0}")]
     FileReadError(#[from] io::Error),
-    #[error("Error parsing file")]
-    ParseError,
+    #[error("Error parsing file: {0}")]
+    ParseError(#[from] toml::de::Error),
+    #[error("Could not find the config directory")]
+    NoConfigDirectory,
+    #[error("Jira host {0:?} is not a URL such as \"https://example.atlassian.net\"")]
+    InvalidHost(String),
     #[error("Something else")]
     OtherError,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnostics_default_to_flagging_done_tickets() {
+        let config: Config = toml::from_str(
+            r#"
+            [jira]
+            host = "https://example.atlassian.net"
+            email = "me@example.com"
+            api_token = "token"
+            "#,
+        )
+        .unwrap();
+
+        let diagnostics = config.diagnostics;
+        assert_eq!(diagnostics.missing_ticket, DiagnosticLevel::Hint);
+        assert_eq!(
+            diagnostics.severity_for_status("Done", "done"),
+            DiagnosticLevel::Warning
+        );
+        assert_eq!(
+            diagnostics.severity_for_status("In Progress", "indeterminate"),
+            DiagnosticLevel::Off
+        );
+    }
+
+    #[test]
+    fn invalid_templates_fail_loading_the_config() {
+        let config = toml::from_str::<Config>(
+            r#"
+            [jira]
+            host = "https://example.atlassian.net"
+            email = "me@example.com"
+            api_token = "token"
+
+            [templates]
+            inlay_hint = ": {titel}"
+            "#,
+        );
+
+        let error = config.err().unwrap().to_string();
+        assert!(error.contains("`{titel}` is not a ticket field"), "{error}");
+    }
+
+    #[test]
+    fn custom_fields_are_mapped_by_id() {
+        let config: Config = toml::from_str(
+            r#"
+            [jira]
+            host = "https://example.atlassian.net"
+            email = "me@example.com"
+            api_token = "token"
+
+            [jira.custom_fields]
+            customfield_10016 = { name = "Story points", type = "number" }
+            customfield_10020 = { name = "Sprint", type = "option_list" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.jira.custom_fields.get("customfield_10020"),
+            Some(&CustomFieldConfig {
+                name: "Sprint".to_owned(),
+                field_type: CustomFieldType::OptionList,
+            })
+        );
+        assert_eq!(config.jira.custom_fields.len(), 2);
+    }
+
+    #[test]
+    fn only_listed_projects_are_configured() {
+        let config: Config = toml::from_str(
+            r#"
+            [jira]
+            host = "https://example.atlassian.net"
+            email = "me@example.com"
+            api_token = "token"
+            projects = ["AUTO", "OPS"]
+            other_projects = "ignore"
+            "#,
+        )
+        .unwrap();
+
+        assert!(config.jira.is_configured_project("AUTO-12"));
+        assert!(config.jira.is_configured_project("OPS-3"));
+        assert!(!config.jira.is_configured_project("SHA-256"));
+        assert!(!config.jira.is_configured_project("AUTOMATION-1"));
+        assert_eq!(config.jira.other_projects, OtherProjects::Ignore);
+    }
+
+    #[test]
+    fn every_project_is_configured_by_default() {
+        let config: Config = toml::from_str(
+            r#"
+            [jira]
+            host = "https://example.atlassian.net"
+            email = "me@example.com"
+            api_token = "token"
+            "#,
+        )
+        .unwrap();
+
+        assert!(config.jira.is_configured_project("SHA-256"));
+        assert_eq!(config.jira.other_projects, OtherProjects::FetchOnDemand);
+    }
+
+    #[test]
+    fn hosts_must_be_urls() {
+        let jira_config = |host: &str| -> JiraConfig {
+            toml::from_str(&format!(
+                r#"
+                host = "{host}"
+                email = "me@example.com"
+                api_token = "token"
+                "#
+            ))
+            .unwrap()
+        };
+
+        assert!(jira_config("https://example.atlassian.net")
+            .check_host()
+            .is_ok());
+        assert!(jira_config("https://jira.example.com/jira/")
+            .check_host()
+            .is_ok());
+        assert!(matches!(
+            jira_config("example.atlassian.net").check_host(),
+            Err(ConfigError::InvalidHost(_))
+        ));
+        assert!(matches!(
+            jira_config("mailto:me@example.com").check_host(),
+            Err(ConfigError::InvalidHost(_))
+        ));
+    }
+
+    #[test]
+    fn status_names_take_precedence_over_categories() {
+        let diagnostics: DiagnosticsConfig = toml::from_str(
+            r#"
+            status_categories = { done = "hint" }
+            statuses = { "Won't Do" = "error", "Released" = "off" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            diagnostics.severity_for_status("won't do", "done"),
+            DiagnosticLevel::Error
+        );
+        assert_eq!(
+            diagnostics.severity_for_status("Released", "done"),
+            DiagnosticLevel::Off
+        );
+        assert_eq!(
+            diagnostics.severity_for_status("Closed", "done"),
+            DiagnosticLevel::Hint
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use lsp_types::{Position, Range, TextDocumentContentChangeEvent, Uri};
+
+/// The in-memory contents of a document the client has open.
+pub struct TextDocument {
+    pub version: i32,
+    pub text: String,
+}
+
+impl TextDocument {
+    /// The text on the line of `position` that comes before it.
+    pub fn line_before(&self, position: Position) -> &str {
+        let end = byte_offset_of_position(&self.text, position);
+        let start = self.text[..end].rfind('\n').map_or(0, |offset| offset + 1);
+        &self.text[start..end]
+    }
+
+    pub fn text_in_range(&self, range: Range) -> &str {
+        let start = byte_offset_of_position(&self.text, range.start);
+        let end = byte_offset_of_position(&self.text, range.end).max(start);
+        &self.text[start..end]
+    }
+}
+
+/// Keeps track of every document the client has opened, kept in sync through the
+/// `textDocument/didOpen`, `textDocument/didChange` and `textDocument/didClose` notifications.
+pub struct DocumentStore {
+    documents: HashMap<Uri, TextDocument>,
+}
+
+impl DocumentStore {
+    pub fn new() -> DocumentStore {
+        DocumentStore {
+            documents: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, uri: &Uri) -> Option<&TextDocument> {
+        self.documents.get(uri)
+    }
+
+    pub fn uris(&self) -> impl Iterator<Item = &Uri> {
+        self.documents.keys()
+    }
+
+    pub fn open(&mut self, uri: Uri, version: i32, text: String) -> &TextDocument {
+        self.documents
+            .insert(uri.clone(), TextDocument { version, text });
+        &self.documents[&uri]
+    }
+
+    /// Applies the changes, in order, to an open document.
+    /// Returns `None` if the client never opened the document.
+    pub fn change(
+        &mut self,
+        uri: &Uri,
+        version: i32,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Option<&TextDocument> {
+        let document = self.documents.get_mut(uri)?;
+        for change in changes {
+            match change.range {
+                Some(range) => {
+                    let start = byte_offset_of_position(&document.text, range.start);
+                    let end = byte_offset_of_position(&document.text, range.end).max(start);
+                    document.text.replace_range(start..end, &change.text);
+                }
+                None => document.text = change.text,
+            }
+        }
+        document.version = version;
+        Some(document)
+    }
+
+    pub fn close(&mut self, uri: &Uri) {
+        self.documents.remove(uri);
+    }
+}
+
+/// Converts an LSP position, whose character offset counts UTF-16 code units,
+/// into a byte offset into `text`. Positions past the end of a line or of the
+/// document are clamped, as the specification requires.
+fn byte_offset_of_position(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(offset) => line_start += offset + 1,
+            None => return text.len(),
+        }
+    }
+    let line = &text[line_start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+
+    let mut utf16_offset = 0;
+    for (byte_offset, character) in line.char_indices() {
+        if utf16_offset >= position.character {
+            return line_start + byte_offset;
+        }
+        utf16_offset += character.len_utf16() as u32;
+    }
+    line_start + line.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn change(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range {
+                start: Position::new(start.0, start.1),
+                end: Position::new(end.0, end.1),
+            }),
+            range_length: None,
+            text: text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn applies_incremental_changes_in_order() {
+        let uri = Uri::from_str("file:///tmp/notes.md").unwrap();
+        let mut store = DocumentStore::new();
+        store.open(uri.clone(), 1, "TODO\nfix it\n".to_owned());
+
+        let document = store
+            .change(
+                &uri,
+                2,
+                vec![
+                    change((0, 4), (0, 4), "(AUTO-12)"),
+                    change((1, 0), (1, 3), "break"),
+                ],
+            )
+            .unwrap();
+
+        assert_eq!(document.text, "TODO(AUTO-12)\nbreak it\n");
+        assert_eq!(document.version, 2);
+    }
+
+    #[test]
+    fn full_change_replaces_text() {
+        let uri = Uri::from_str("untitled:Untitled-1").unwrap();
+        let mut store = DocumentStore::new();
+        store.open(uri.clone(), 1, "old".to_owned());
+
+        let document = store
+            .change(
+                &uri,
+                2,
+                vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: "new".to_owned(),
+                }],
+            )
+            .unwrap();
+
+        assert_eq!(document.text, "new");
+    }
+
+    #[test]
+    fn change_positions_count_utf16_code_units() {
+        let uri = Uri::from_str("file:///tmp/emoji.txt").unwrap();
+        let mut store = DocumentStore::new();
+        store.open(uri.clone(), 1, "🎉é ABC-1".to_owned());
+
+        let document = store
+            .change(&uri, 2, vec![change((0, 4), (0, 9), "XYZ-2")])
+            .unwrap();
+
+        assert_eq!(document.text, "🎉é XYZ-2");
+    }
+
+    #[test]
+    fn line_before_stops_at_position() {
+        let document = TextDocument {
+            version: 1,
+            text: "first\n// AUTO-12 rest".to_owned(),
+        };
+
+        assert_eq!(document.line_before(Position::new(1, 10)), "// AUTO-12");
+        assert_eq!(document.line_before(Position::new(0, 0)), "");
+    }
+
+    #[test]
+    fn text_in_range_clamps_to_line_end() {
+        let document = TextDocument {
+            version: 1,
+            text: "first\n// AUTO-12 rest\nlast".to_owned(),
+        };
+
+        let whole_line = Range::new(Position::new(1, 0), Position::new(1, u32::MAX));
+        assert_eq!(document.text_in_range(whole_line), "// AUTO-12 rest");
+    }
+
+    #[test]
+    fn change_to_unopened_document_is_ignored() {
+        let uri = Uri::from_str("file:///tmp/closed.txt").unwrap();
+        let mut store = DocumentStore::new();
+
+        assert!(store
+            .change(&uri, 2, vec![change((0, 0), (0, 0), "a")])
+            .is_none());
+    }
+}
@@ -0,0 +1,62 @@
+/// Scores how well `query` matches `candidate` when its characters are typed in order,
+/// ignoring case. Returns `None` if some character of the query is missing.
+///
+/// Higher scores are better matches: consecutive characters and characters at the start
+/// of a word are rewarded, gaps between matched characters are penalised.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut candidate_chars = candidate.chars().enumerate();
+    let mut previous_match: Option<usize> = None;
+    let mut previous_char: Option<char> = None;
+
+    for query_char in query.chars().filter(|c| !c.is_whitespace()) {
+        let query_char = query_char.to_lowercase().next()?;
+        loop {
+            let (index, candidate_char) = candidate_chars.next()?;
+            let is_word_start = previous_char.is_none_or(|c| !c.is_alphanumeric());
+            previous_char = Some(candidate_char);
+            if candidate_char.to_lowercase().next() != Some(query_char) {
+                continue;
+            }
+            score += 1;
+            if is_word_start {
+                score += 8;
+            }
+            match previous_match {
+                Some(previous) if previous + 1 == index => score += 5,
+                Some(previous) => score -= (index - previous - 1).min(5) as i64,
+                None => score -= index.min(5) as i64,
+            }
+            previous_match = Some(index);
+            break;
+        }
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_characters_do_not_match() {
+        assert_eq!(fuzzy_score("xyz", "Fix login bug"), None);
+        assert_eq!(fuzzy_score("bugfix", "Fix login bug"), None);
+    }
+
+    #[test]
+    fn matching_ignores_case_and_whitespace() {
+        assert!(fuzzy_score("LOGIN bug", "Fix login bug").is_some());
+    }
+
+    #[test]
+    fn prefers_word_starts_and_consecutive_characters() {
+        let word_start = fuzzy_score("log", "Fix login bug").unwrap();
+        let scattered = fuzzy_score("log", "Allow big graphs").unwrap();
+        assert!(word_start > scattered);
+
+        let acronym = fuzzy_score("flb", "Fix login bug").unwrap();
+        let mid_word = fuzzy_score("flb", "Reflowable").unwrap();
+        assert!(acronym > mid_word);
+    }
+}
@@ -1,33 +1,196 @@
-use crate::atlassian_markup_transpiler::transpile_atlassian_markup_to_markdown;
-use crate::config::JiraConfig;
-use gouqi::{Credentials, Error, Issue, Jira};
+use crate::config::{CustomFieldConfig, CustomFieldType, JiraConfig, NetworkConfig};
+use crate::request_guard::RequestGuard;
+use gouqi::{
+    AddComment, Error, Errors, Issue, SearchOptions, SearchOptionsBuilder, SearchResults,
+    TransitionOptions, TransitionTriggerOptions,
+};
 use log::warn;
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::{HeaderMap, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::{StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::cmp::Reverse;
 use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+use time::macros::format_description;
+use time::OffsetDateTime;
 
+const PROJECT_SEARCH_LIMIT: u64 = 100;
+/// Key numbers are completed up to this many digits, as JQL can not match the start of a
+/// key.
+const LONGEST_KEY_NUMBER: usize = 6;
+/// How many tickets are asked for in one `key in (...)` search, keeping its JQL short.
+const TICKET_BATCH_SIZE: usize = 50;
+
+/// A ticket as fetched from Jira. The description is kept as Atlassian markup.
+///
+/// Fields added since tickets were first cached default to empty, so that older caches
+/// still load.
+#[derive(Serialize, Deserialize, Default)]
 pub struct JiraTicket {
     pub key: String,
     pub title: String,
     pub description: String,
+    /// The display name of the assignee.
     pub assignee: Option<String>,
+    /// Jira only shares this if the assignee's profile visibility allows it.
+    pub assignee_email: Option<String>,
+    /// The account id of the assignee on Jira Cloud, or their user name on Jira Server.
+    #[serde(default)]
+    pub assignee_id: Option<String>,
     pub status: String,
+    /// The key of the status category: `new`, `indeterminate` or `done`.
+    pub status_category: String,
+    /// The display name of the reporter.
+    #[serde(default)]
+    pub reporter: Option<String>,
+    #[serde(default)]
+    pub priority: Option<String>,
+    #[serde(default)]
+    pub issue_type: Option<String>,
+    /// Dates are `YYYY-MM-DD`, as Jira shows them.
+    #[serde(default)]
+    pub created: Option<String>,
+    #[serde(default)]
+    pub updated: Option<String>,
+    #[serde(default)]
+    pub due: Option<String>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub components: Vec<String>,
+    #[serde(default)]
+    pub fix_versions: Vec<String>,
+    /// The most recent comments, newest first. Only fetched once the ticket is hovered,
+    /// `None` until then.
+    #[serde(default)]
+    pub comments: Option<Vec<TicketComment>>,
+    /// The parent, such as the epic the ticket belongs to.
+    #[serde(default)]
+    pub parent: Option<LinkedTicket>,
+    /// Issue links, such as the tickets this one blocks or is blocked by.
+    #[serde(default)]
+    pub links: Vec<LinkedTicket>,
+    #[serde(default)]
+    pub subtasks: Vec<LinkedTicket>,
+    /// How many children of an epic are done. Only counted once the ticket is hovered,
+    /// `None` until then and for tickets that are not epics.
+    #[serde(default)]
+    pub child_progress: Option<ChildProgress>,
+    /// The fields configured in `jira.custom_fields` that the ticket has a value for.
+    #[serde(default)]
+    pub custom_fields: Vec<CustomFieldValue>,
 }
 
-impl ToString for JiraTicket {
-    fn to_string(&self) -> String {
-        format!(
-            r"
-# {}
----
-󱖫 {} | 󰃭 2024-12-31 |  {}
-
----
-{}
-",
-            self.title,
-            self.status,
-            self.assignee.as_ref().map_or("Unassigned", |x| x.as_str()),
-            self.description
+/// Another ticket as mentioned by this one, with just enough to list it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LinkedTicket {
+    /// How this ticket relates to it, e.g. `blocks`, `is blocked by`, `parent` or `subtask`.
+    pub relation: String,
+    pub key: String,
+    pub title: String,
+    pub status: String,
+    pub status_category: String,
+    pub issue_type: Option<String>,
+    /// Counted once the ticket is hovered, for parents that are epics.
+    #[serde(default)]
+    pub child_progress: Option<ChildProgress>,
+}
+
+impl LinkedTicket {
+    pub fn is_epic(&self) -> bool {
+        is_epic(self.issue_type.as_deref())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ChildProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+fn is_epic(issue_type: Option<&str>) -> bool {
+    issue_type.is_some_and(|issue_type| issue_type.eq_ignore_ascii_case("epic"))
+}
+
+/// The value of a custom field, as text. Markup is kept as Atlassian markup, and the
+/// options of an option list are joined with commas.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CustomFieldValue {
+    pub name: String,
+    pub field_type: CustomFieldType,
+    pub value: String,
+}
+
+/// A comment on a ticket. The body is kept as Atlassian markup.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TicketComment {
+    pub author: String,
+    pub created: Option<SystemTime>,
+    pub body: String,
+}
+
+impl JiraTicket {
+    pub fn is_epic(&self) -> bool {
+        is_epic(self.issue_type.as_deref())
+    }
+
+    /// The details shown under the title as a Markdown list, leaving out the ones the
+    /// ticket does not have. Custom fields of markup are too long to be listed.
+    pub fn details(&self) -> String {
+        let lists = [
+            ("Labels", &self.labels),
+            ("Components", &self.components),
+            ("Fix versions", &self.fix_versions),
+        ];
+        [
+            ("Type", self.issue_type.to_owned()),
+            ("Priority", self.priority.to_owned()),
+            ("Reporter", self.reporter.to_owned()),
+            ("Created", self.created.to_owned()),
+            ("Due", self.due.to_owned()),
+        ]
+        .into_iter()
+        .chain(lists.map(|(name, values)| (name, (!values.is_empty()).then(|| values.join(", ")))))
+        .chain(
+            self.custom_fields
+                .iter()
+                .filter(|custom_field| custom_field.field_type != CustomFieldType::Markup)
+                .map(|custom_field| {
+                    (
+                        custom_field.name.as_str(),
+                        Some(custom_field.value.to_owned()),
+                    )
+                }),
         )
+        .filter_map(|(name, value)| Some(format!("- **{name}:** {}\n", value?)))
+        .collect()
+    }
+}
+
+/// Builds the tickets the tests of every module use.
+#[cfg(test)]
+impl JiraTicket {
+    /// "Fix login bug", not started yet.
+    pub fn test_ticket(key: &str) -> JiraTicket {
+        JiraTicket {
+            key: key.to_owned(),
+            title: "Fix login bug".to_owned(),
+            status: "To Do".to_owned(),
+            status_category: "new".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_status(self, status: &str, status_category: &str) -> JiraTicket {
+        JiraTicket {
+            status: status.to_owned(),
+            status_category: status_category.to_owned(),
+            ..self
+        }
     }
 }
 
@@ -49,66 +212,1050 @@ impl TryFrom<Issue> for JiraTicket {
             .field::<Option<String>>("description")
             .transpose()?
             .flatten()
-            .map(|mut x| {
-                x.push_str("\n\nHere is transpiled:\n\n");
-                x.push_str(transpile_atlassian_markup_to_markdown(x.as_str()).as_str());
-                x
-            })
             .unwrap_or("No description".to_owned());
-        let status = ticket
+        let status_field = ticket
             .field::<BTreeMap<String, ::serde_json::Value>>("status")
-            .unwrap()?
+            .ok_or_else(|| missing_field(&ticket.key, "status"))??;
+        let status = status_field
             .get("name")
-            .map(|value| serde_json::value::from_value::<String>(value.clone()))
-            .unwrap();
-        let status = match status {
-            Ok(value) => value,
-            Err(error) => return Err(Error::Serde(error)),
-        };
+            .ok_or_else(|| missing_field(&ticket.key, "status.name"))?;
+        let status = serde_json::value::from_value::<String>(status.clone())?;
+        let status_category = status_field
+            .get("statusCategory")
+            .and_then(|category| category.get("key"))
+            .and_then(|key| key.as_str())
+            .unwrap_or("undefined")
+            .to_owned();
+        let assignee = ticket.assignee();
 
         Ok(JiraTicket {
-            key: ticket.key,
             title,
             description,
-            assignee: None,
+            assignee: assignee.as_ref().map(|user| user.display_name.to_owned()),
+            assignee_email: assignee.and_then(|user| user.email_address),
+            assignee_id: ticket
+                .fields
+                .get("assignee")
+                .and_then(user_id)
+                .map(str::to_owned),
             status,
+            status_category,
+            reporter: ticket.reporter().map(|user| user.display_name),
+            priority: name_field(&ticket, "priority"),
+            issue_type: name_field(&ticket, "issuetype"),
+            created: date_field(&ticket, "created"),
+            updated: date_field(&ticket, "updated"),
+            due: date_field(&ticket, "duedate"),
+            labels: ticket.labels(),
+            components: name_list_field(&ticket, "components"),
+            fix_versions: name_list_field(&ticket, "fixVersions"),
+            comments: None,
+            parent: ticket
+                .fields
+                .get("parent")
+                .and_then(|parent| linked_ticket(parent, String::from("parent"))),
+            links: issue_links(&ticket),
+            subtasks: ticket
+                .fields
+                .get("subtasks")
+                .and_then(|subtasks| subtasks.as_array())
+                .map_or(Vec::new(), |subtasks| {
+                    subtasks
+                        .iter()
+                        .filter_map(|subtask| linked_ticket(subtask, String::from("subtask")))
+                        .collect()
+                }),
+            child_progress: None,
+            custom_fields: Vec::new(),
+            key: ticket.key,
         })
     }
 }
 
+/// The outcome of asking Jira for a single ticket.
+#[derive(Serialize, Deserialize)]
+pub enum TicketLookup {
+    Found(Box<JiraTicket>),
+    /// Jira does not tell apart tickets that do not exist and those the user can not see.
+    NotFound,
+    Forbidden,
+}
+
+/// A workflow transition that can currently be applied to a ticket.
+pub struct TicketTransition {
+    pub id: String,
+    pub name: String,
+    pub to_status: String,
+}
+
+/// Talks to Jira's REST API through `client` directly rather than through gouqi, so that
+/// `Retry-After` can be read from responses. gouqi's types are still used for the tickets.
 pub struct JiraResolver {
-    jira: Jira,
+    client: Client,
+    /// The host, ending in a `/` so that the API is found under any context path.
+    api_base_url: Url,
+    request_guard: RequestGuard,
+    custom_fields: BTreeMap<String, CustomFieldConfig>,
+    host: String,
+    email: String,
+    api_token: String,
+    /// The user the API token belongs to, fetched once.
+    myself: OnceLock<serde_json::Value>,
 }
 
 impl JiraResolver {
-    pub fn new(jira_config: &JiraConfig) -> JiraResolver {
-        JiraResolver {
-            jira: Jira::new(
-                jira_config.host.to_owned(),
-                Credentials::Basic(
-                    jira_config.email.to_owned(),
-                    jira_config.api_token.to_owned(),
-                ),
-            )
-            .expect("err with jira connection"),
-        }
-    }
-
-    pub fn get_jira_tickets(&self) -> HashMap<String, JiraTicket> {
-        self.jira
-            .search()
-            .iter("project = AUTO", &Default::default())
-            .expect("error in jira")
-            .filter_map(|issue| {
-                let key = issue.key.to_owned();
-                match <Issue as TryInto<JiraTicket>>::try_into(issue) {
-                    Ok(ticket) => Some((ticket.key.to_owned(), ticket)),
-                    Err(e) => {
-                        warn!("Dropping ticket {} because {:?}", key, e);
-                        None
+    pub fn new(
+        jira_config: &JiraConfig,
+        network_config: &NetworkConfig,
+    ) -> Result<JiraResolver, Error> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(network_config.request_timeout())
+            .build()?;
+        let host = jira_config.host.trim_end_matches('/').to_owned();
+        Ok(JiraResolver {
+            client,
+            api_base_url: Url::parse(&format!("{host}/"))?,
+            request_guard: RequestGuard::new(network_config),
+            custom_fields: jira_config.custom_fields.to_owned(),
+            host,
+            email: jira_config.email.to_owned(),
+            api_token: jira_config.api_token.to_owned(),
+            myself: OnceLock::new(),
+        })
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn get<D: DeserializeOwned>(&self, endpoint: &str, query: &[(&str, &str)]) -> Result<D, Error> {
+        let mut url = self.api_url(endpoint)?;
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+        self.send(self.client.get(url))
+    }
+
+    fn post<D: DeserializeOwned>(&self, endpoint: &str, body: &impl Serialize) -> Result<D, Error> {
+        let url = self.api_url(endpoint)?;
+        self.send(self.client.post(url).body(serde_json::to_vec(body)?))
+    }
+
+    fn put<D: DeserializeOwned>(&self, endpoint: &str, body: &impl Serialize) -> Result<D, Error> {
+        let url = self.api_url(endpoint)?;
+        self.send(self.client.put(url).body(serde_json::to_vec(body)?))
+    }
+
+    fn api_url(&self, endpoint: &str) -> Result<Url, Error> {
+        Ok(self
+            .api_base_url
+            .join(&format!("rest/api/latest{endpoint}"))?)
+    }
+
+    /// When Jira asks to be left alone for a while with `Retry-After`, every request waits
+    /// that long.
+    fn send<D: DeserializeOwned>(&self, request: RequestBuilder) -> Result<D, Error> {
+        let response = request
+            .header(CONTENT_TYPE, "application/json")
+            .basic_auth(&self.email, Some(&self.api_token))
+            .send()?;
+        if let Some(retry_after) = retry_after(response.headers()) {
+            self.request_guard.hold_off(retry_after);
+        }
+        let status = response.status();
+        read_response(status, response.text()?)
+    }
+
+    fn search(&self, jql: &str, search_options: &SearchOptions) -> Result<SearchResults, Error> {
+        let endpoint = match search_options.serialize() {
+            Some(options) => format!("/search?{options}"),
+            None => String::from("/search"),
+        };
+        self.get(&endpoint, &[("jql", jql)])
+    }
+
+    /// Jira Cloud hides email addresses by default, so the assignee is told apart by id
+    /// once the user is known, and by email address only until then.
+    pub fn is_assigned_to_me(&self, ticket: &JiraTicket) -> bool {
+        match (self.myself.get().and_then(user_id), &ticket.assignee_id) {
+            (Some(my_id), Some(assignee_id)) => my_id == assignee_id,
+            _ => ticket
+                .assignee_email
+                .as_ref()
+                .is_some_and(|email| email.eq_ignore_ascii_case(&self.email)),
+        }
+    }
+
+    /// Only asks Jira once, after which the same user is handed out.
+    pub fn fetch_myself(&self) -> Result<&serde_json::Value, Error> {
+        if let Some(myself) = self.myself.get() {
+            return Ok(myself);
+        }
+        let myself = self
+            .request_guard
+            .call(|| self.get::<serde_json::Value>("/myself", &[]))?;
+        Ok(self.myself.get_or_init(|| myself))
+    }
+
+    /// Looks the tickets up with as few searches as possible, calling `on_batch` with how
+    /// many of the keys have been looked up so far.
+    ///
+    /// Searches quietly leave out tickets that do not exist or can not be seen, so those
+    /// are looked up one by one to tell which it is. Keys whose batch failed are left out,
+    /// so that they are tried again later.
+    pub fn fetch_tickets(
+        &self,
+        keys: &[String],
+        mut on_batch: impl FnMut(usize, usize),
+    ) -> HashMap<String, TicketLookup> {
+        let mut lookups = HashMap::new();
+        for (batch_number, batch) in keys.chunks(TICKET_BATCH_SIZE).enumerate() {
+            match self.search_tickets_by_key(batch) {
+                Ok(tickets) => {
+                    for ticket in tickets {
+                        lookups
+                            .insert(ticket.key.to_owned(), TicketLookup::Found(Box::new(ticket)));
                     }
+                    for key in batch {
+                        if lookups.contains_key(key) {
+                            continue;
+                        }
+                        match self.lookup_ticket(key) {
+                            Ok(lookup) => {
+                                lookups.insert(key.to_owned(), lookup);
+                            }
+                            Err(e) => warn!("Could not look up {key} because {e:?}"),
+                        }
+                    }
+                }
+                Err(e) => warn!("Could not fetch {} because {e:?}", batch.join(", ")),
+            }
+            on_batch(
+                (batch_number * TICKET_BATCH_SIZE + batch.len()).min(keys.len()),
+                keys.len(),
+            );
+        }
+        lookups
+    }
+
+    fn search_tickets_by_key(&self, keys: &[String]) -> Result<Vec<JiraTicket>, Error> {
+        let quoted_keys: Vec<String> = keys.iter().map(|key| format!("\"{key}\"")).collect();
+        let search_options = unvalidated_search_options()
+            .max_results(keys.len() as u64)
+            .build();
+        let jql = format!("key in ({})", quoted_keys.join(", "));
+        Ok(self
+            .request_guard
+            .call(|| self.search(&jql, &search_options))?
+            .issues
+            .into_iter()
+            .filter_map(|issue| issue_into_ticket(issue, &self.custom_fields))
+            .collect())
+    }
+
+    /// Pages through every ticket the JQL matches, calling `on_page` with how many of how
+    /// many tickets have been fetched so far.
+    pub fn search_tickets(
+        &self,
+        jql: &str,
+        on_page: impl FnMut(usize, usize),
+    ) -> Result<Vec<JiraTicket>, Error> {
+        self.search_all_tickets(jql, &mut SearchOptions::builder(), on_page)
+    }
+
+    fn search_all_tickets(
+        &self,
+        jql: &str,
+        search_options: &mut SearchOptionsBuilder,
+        mut on_page: impl FnMut(usize, usize),
+    ) -> Result<Vec<JiraTicket>, Error> {
+        let mut tickets = Vec::new();
+        let mut fetched = 0;
+        loop {
+            let search_options = search_options.start_at(fetched as u64).build();
+            let results = self
+                .request_guard
+                .call(|| self.search(jql, &search_options))?;
+            if results.issues.is_empty() {
+                break;
+            }
+            fetched += results.issues.len();
+            tickets.extend(
+                results
+                    .issues
+                    .into_iter()
+                    .filter_map(|issue| issue_into_ticket(issue, &self.custom_fields)),
+            );
+            let total = results.total as usize;
+            on_page(fetched, total);
+            if fetched >= total {
+                break;
+            }
+        }
+        Ok(tickets)
+    }
+
+    /// The tickets of the projects, and the tickets with the keys, that changed since
+    /// `since`. Fails as a whole, so that a partial sync is never taken for a full one.
+    pub fn fetch_updated_tickets(
+        &self,
+        projects: &[String],
+        keys: &[String],
+        since: SystemTime,
+    ) -> Result<Vec<JiraTicket>, Error> {
+        let updated_since = updated_since_jql(since);
+        let mut tickets = Vec::new();
+        if !projects.is_empty() {
+            tickets.extend(
+                self.search_tickets(&jql_in_projects(&updated_since, projects), |_, _| ())?,
+            );
+        }
+        for batch in keys.chunks(TICKET_BATCH_SIZE) {
+            let quoted_keys: Vec<String> = batch.iter().map(|key| format!("\"{key}\"")).collect();
+            // A tracked ticket that has since been deleted must not fail every sync.
+            tickets.extend(self.search_all_tickets(
+                &format!("key in ({}) AND {updated_since}", quoted_keys.join(", ")),
+                &mut unvalidated_search_options(),
+                |_, _| (),
+            )?);
+        }
+        Ok(tickets)
+    }
+
+    /// The most recently updated tickets of a project matching what has been typed of a
+    /// refrence to one, for completing it.
+    pub fn search_project_tickets(&self, project_key: &str, query: &str) -> Vec<JiraTicket> {
+        let search_options = unvalidated_search_options()
+            .max_results(PROJECT_SEARCH_LIMIT)
+            .build();
+        let jql = project_search_jql(project_key, query);
+        match self
+            .request_guard
+            .call(|| self.search(&jql, &search_options))
+        {
+            Ok(results) => results
+                .issues
+                .into_iter()
+                .filter_map(|issue| issue_into_ticket(issue, &self.custom_fields))
+                .collect(),
+            Err(e) => {
+                warn!("Could not search project {project_key} because {e:?}");
+                Vec::new()
+            }
+        }
+    }
+
+    pub fn lookup_ticket(&self, key: &str) -> Result<TicketLookup, Error> {
+        match self
+            .request_guard
+            .call(|| self.get::<Issue>(&format!("/issue/{key}"), &[]))
+        {
+            Ok(issue) => Ok(TicketLookup::Found(Box::new(ticket_from_issue(
+                issue,
+                &self.custom_fields,
+            )?))),
+            Err(Error::NotFound) => Ok(TicketLookup::NotFound),
+            Err(Error::Fault { code, .. }) if code.as_u16() == 403 => Ok(TicketLookup::Forbidden),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get_transitions(&self, key: &str) -> Result<Vec<TicketTransition>, Error> {
+        Ok(self
+            .request_guard
+            .call(|| {
+                self.get::<TransitionOptions>(
+                    &format!("/issue/{key}/transitions?expand=transitions.fields"),
+                    &[],
+                )
+            })?
+            .transitions
+            .into_iter()
+            .map(|transition| TicketTransition {
+                id: transition.id,
+                name: transition.name,
+                to_status: transition.to.name,
+            })
+            .collect())
+    }
+
+    pub fn transition_ticket(&self, key: &str, transition_id: &str) -> Result<(), Error> {
+        self.request_guard.call_once(|| {
+            self.post::<serde_json::Value>(
+                &format!("/issue/{key}/transitions"),
+                &TransitionTriggerOptions::new(transition_id),
+            )
+        })?;
+        Ok(())
+    }
+
+    pub fn assign_ticket_to_me(&self, key: &str) -> Result<(), Error> {
+        let myself = self.fetch_myself()?;
+        // Jira Cloud identifies users by account id, Jira Server by user name.
+        let assignee = match (myself.get("accountId"), myself.get("name")) {
+            (Some(account_id), _) => json!({ "accountId": account_id }),
+            (None, Some(name)) => json!({ "name": name }),
+            (None, None) => json!({ "name": self.email }),
+        };
+        self.request_guard
+            .call(|| self.put::<serde_json::Value>(&format!("/issue/{key}/assignee"), &assignee))?;
+        Ok(())
+    }
+
+    /// The tickets of an epic, or the subtasks of any other ticket.
+    pub fn fetch_children(&self, key: &str) -> Result<Vec<JiraTicket>, Error> {
+        self.search_tickets(&format!("parent = \"{key}\""), |_, _| ())
+    }
+
+    /// Counts the children of the epic, by how many of them are done.
+    pub fn fetch_child_progress(&self, epic_key: &str) -> Result<ChildProgress, Error> {
+        let children = self.fetch_children(epic_key)?;
+        Ok(ChildProgress {
+            done: children
+                .iter()
+                .filter(|child| child.status_category == "done")
+                .count(),
+            total: children.len(),
+        })
+    }
+
+    /// The most recent comments on the ticket, newest first. Jira Server ignores `orderBy`,
+    /// so comments are asked for oldest first and, when there are more than `count` of
+    /// them, asked for again from the end.
+    pub fn fetch_comments(&self, key: &str, count: usize) -> Result<Vec<TicketComment>, Error> {
+        let fetch_page = |start_at: usize| {
+            self.request_guard.call(|| {
+                self.get::<serde_json::Value>(
+                    &format!("/issue/{key}/comment?startAt={start_at}&maxResults={count}"),
+                    &[],
+                )
+            })
+        };
+        let mut page = fetch_page(0)?;
+        let total = page
+            .get("total")
+            .and_then(|total| total.as_u64())
+            .map_or(0, |total| total as usize);
+        if total > count {
+            page = fetch_page(total - count)?;
+        }
+        let mut comments: Vec<TicketComment> = page
+            .get("comments")
+            .and_then(|comments| comments.as_array())
+            .map_or(Vec::new(), |comments| {
+                comments.iter().map(comment_from_json).collect()
+            });
+        comments.sort_by_key(|comment| Reverse(comment.created));
+        comments.truncate(count);
+        Ok(comments)
+    }
+
+    pub fn add_comment(&self, key: &str, body: String) -> Result<(), Error> {
+        self.request_guard.call_once(|| {
+            self.post::<serde_json::Value>(
+                &format!("/issue/{key}/comment"),
+                &AddComment {
+                    body: body.to_owned(),
+                },
+            )
+        })?;
+        Ok(())
+    }
+}
+
+fn missing_field(key: &str, field: &str) -> Error {
+    Error::Serde(serde::de::Error::custom(format!(
+        "ticket {key} has no {field}"
+    )))
+}
+
+/// Without validation Jira skips keys that do not exist, and keys of projects that do not,
+/// rather than failing the whole search.
+fn unvalidated_search_options() -> SearchOptionsBuilder {
+    let mut search_options = SearchOptions::builder();
+    search_options.validate_query(false);
+    search_options
+}
+
+/// The account id of a user on Jira Cloud, or their user name on Jira Server.
+fn user_id(user: &serde_json::Value) -> Option<&str> {
+    user.get("accountId").or_else(|| user.get("name"))?.as_str()
+}
+
+/// Maps the status to an error as gouqi does, except that server errors are faults too
+/// rather than bodies that fail to parse.
+fn read_response<D: DeserializeOwned>(status: StatusCode, body: String) -> Result<D, Error> {
+    match status {
+        StatusCode::UNAUTHORIZED => Err(Error::Unauthorized),
+        StatusCode::METHOD_NOT_ALLOWED => Err(Error::MethodNotAllowed),
+        StatusCode::NOT_FOUND => Err(Error::NotFound),
+        status if status.is_client_error() || status.is_server_error() => Err(Error::Fault {
+            code: status,
+            // Rate limited and failing responses do not always come with Jira's errors.
+            errors: serde_json::from_str(&body).unwrap_or_else(|_| Errors {
+                error_messages: vec![body],
+                errors: BTreeMap::new(),
+            }),
+        }),
+        _ => Ok(serde_json::from_str(if body.is_empty() {
+            "null"
+        } else {
+            &body
+        })?),
+    }
+}
+
+/// Jira sends the number of seconds to wait, rather than a date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// The name of a field holding an object, such as the priority. Read loosely, as
+/// different Jira versions send different companions to the name.
+fn name_field(issue: &Issue, field: &str) -> Option<String> {
+    Some(issue.fields.get(field)?.get("name")?.as_str()?.to_owned())
+}
+
+/// Each link names the other ticket as either inward or outward, and how the two relate
+/// from this ticket's side follows from which.
+fn issue_links(issue: &Issue) -> Vec<LinkedTicket> {
+    let Some(links) = issue
+        .fields
+        .get("issuelinks")
+        .and_then(|links| links.as_array())
+    else {
+        return Vec::new();
+    };
+    links
+        .iter()
+        .filter_map(|link| {
+            let (relation, other_ticket) = match (link.get("outwardIssue"), link.get("inwardIssue"))
+            {
+                (Some(outward_ticket), _) => ("outward", outward_ticket),
+                (None, Some(inward_ticket)) => ("inward", inward_ticket),
+                (None, None) => return None,
+            };
+            let relation = link.get("type")?.get(relation)?.as_str()?;
+            linked_ticket(other_ticket, relation.to_owned())
+        })
+        .collect()
+}
+
+/// Jira sends linked tickets with only a few of their fields.
+fn linked_ticket(linked_ticket: &serde_json::Value, relation: String) -> Option<LinkedTicket> {
+    let fields = linked_ticket.get("fields")?;
+    let status = fields.get("status")?;
+    let text = |value: Option<&serde_json::Value>| value?.as_str().map(str::to_owned);
+    Some(LinkedTicket {
+        relation,
+        key: text(linked_ticket.get("key"))?,
+        title: text(fields.get("summary")).unwrap_or_else(|| "No title".to_owned()),
+        status: text(status.get("name")).unwrap_or_default(),
+        status_category: text(
+            status
+                .get("statusCategory")
+                .and_then(|category| category.get("key")),
+        )
+        .unwrap_or_else(|| "undefined".to_owned()),
+        issue_type: text(
+            fields
+                .get("issuetype")
+                .and_then(|issue_type| issue_type.get("name")),
+        ),
+        child_progress: None,
+    })
+}
+
+fn name_list_field(issue: &Issue, field: &str) -> Vec<String> {
+    issue
+        .fields
+        .get(field)
+        .and_then(|values| values.as_array())
+        .map_or(Vec::new(), |values| {
+            values
+                .iter()
+                .filter_map(|value| Some(value.get("name")?.as_str()?.to_owned()))
+                .collect()
+        })
+}
+
+/// Jira sends due dates as `YYYY-MM-DD` and other dates as full timestamps, of which
+/// only the day is kept.
+fn date_field(issue: &Issue, field: &str) -> Option<String> {
+    let date = issue.fields.get(field)?.as_str()?;
+    Some(date.get(..10).unwrap_or(date).to_owned())
+}
+
+fn comment_from_json(comment: &serde_json::Value) -> TicketComment {
+    let text = |value: Option<&serde_json::Value>| value?.as_str().map(str::to_owned);
+    TicketComment {
+        author: text(
+            comment
+                .get("author")
+                .and_then(|author| author.get("displayName")),
+        )
+        .unwrap_or_else(|| String::from("Someone")),
+        created: text(comment.get("created")).and_then(|created| parse_timestamp(&created)),
+        body: text(comment.get("body")).unwrap_or_default(),
+    }
+}
+
+/// Jira timestamps look like `2024-11-02T09:15:00.000+0000`, which is not quite ISO 8601.
+fn parse_timestamp(timestamp: &str) -> Option<SystemTime> {
+    let format = format_description!(
+        "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond][offset_hour sign:mandatory][offset_minute]"
+    );
+    OffsetDateTime::parse(timestamp, format)
+        .ok()
+        .map(SystemTime::from)
+}
+
+/// Reads the ticket along with the custom fields configured for it.
+fn ticket_from_issue(
+    issue: Issue,
+    custom_fields: &BTreeMap<String, CustomFieldConfig>,
+) -> Result<JiraTicket, Error> {
+    let custom_field_values = custom_field_values(&issue, custom_fields);
+    let mut ticket = JiraTicket::try_from(issue)?;
+    ticket.custom_fields = custom_field_values;
+    Ok(ticket)
+}
+
+fn custom_field_values(
+    issue: &Issue,
+    custom_fields: &BTreeMap<String, CustomFieldConfig>,
+) -> Vec<CustomFieldValue> {
+    custom_fields
+        .iter()
+        .filter_map(|(id, custom_field)| {
+            Some(CustomFieldValue {
+                name: custom_field.name.to_owned(),
+                field_type: custom_field.field_type,
+                value: custom_field_text(issue.fields.get(id)?, custom_field.field_type)?,
+            })
+        })
+        .collect()
+}
+
+/// Values that are empty, or not of the configured type, are left out.
+fn custom_field_text(value: &serde_json::Value, field_type: CustomFieldType) -> Option<String> {
+    let text = match field_type {
+        CustomFieldType::Number => value.as_f64()?.to_string(),
+        CustomFieldType::Text | CustomFieldType::Markup => value.as_str()?.to_owned(),
+        CustomFieldType::User => value.get("displayName")?.as_str()?.to_owned(),
+        CustomFieldType::OptionList => {
+            let options = match value {
+                serde_json::Value::Array(options) => options.iter().collect(),
+                option => vec![option],
+            };
+            options
+                .into_iter()
+                .filter_map(option_name)
+                .collect::<Vec<&str>>()
+                .join(", ")
+        }
+    };
+    (!text.is_empty()).then_some(text)
+}
+
+/// Select list options are named by their `value`, while the likes of sprints have a
+/// `name`.
+fn option_name(option: &serde_json::Value) -> Option<&str> {
+    option
+        .as_str()
+        .or_else(|| option.get("value")?.as_str())
+        .or_else(|| option.get("name")?.as_str())
+}
+
+fn issue_into_ticket(
+    issue: Issue,
+    custom_fields: &BTreeMap<String, CustomFieldConfig>,
+) -> Option<JiraTicket> {
+    let key = issue.key.to_owned();
+    match ticket_from_issue(issue, custom_fields) {
+        Ok(ticket) => Some(ticket),
+        Err(e) => {
+            warn!("Dropping ticket {} because {:?}", key, e);
+            None
+        }
+    }
+}
+
+/// JQL dates are read in the timezone of the user's Jira profile, so the time is given
+/// relative to now instead, with a minute to spare for clocks being out of step.
+fn updated_since_jql(since: SystemTime) -> String {
+    let minutes = since.elapsed().map_or(0, |elapsed| elapsed.as_secs() / 60) + 1;
+    format!("updated >= \"-{minutes}m\"")
+}
+
+/// The page for the ticket in the Jira web interface.
+pub fn browse_url(host: &str, key: &str) -> String {
+    format!("{}/browse/{}", host.trim_end_matches('/'), key)
+}
+
+/// A query of digits matches the start of the number of the key, and any other query the
+/// start of a word in the summary.
+fn project_search_jql(project_key: &str, query: &str) -> String {
+    let filter = if query.chars().all(|character| character.is_ascii_digit()) {
+        key_number_filter(project_key, query)
+    } else {
+        summary_filter(query)
+    };
+    match filter {
+        Some(filter) => {
+            format!("project = \"{project_key}\" AND {filter} ORDER BY updated DESC")
+        }
+        None => format!("project = \"{project_key}\" ORDER BY updated DESC"),
+    }
+}
+
+/// Keys are compared by their number, so the keys starting with the digits are found as
+/// a range for each longer number, e.g. `AUTO-1`, then `AUTO-10` to `AUTO-19` and so on.
+fn key_number_filter(project_key: &str, digits: &str) -> Option<String> {
+    if digits.is_empty() {
+        return None;
+    }
+    let ranges = (1..=LONGEST_KEY_NUMBER.saturating_sub(digits.len())).map(|extra_digits| {
+        format!(
+            "(key >= \"{project_key}-{digits}{}\" AND key <= \"{project_key}-{digits}{}\")",
+            "0".repeat(extra_digits),
+            "9".repeat(extra_digits),
+        )
+    });
+    let filters: Vec<String> = [format!("key = \"{project_key}-{digits}\"")]
+        .into_iter()
+        .chain(ranges)
+        .collect();
+    Some(format!("({})", filters.join(" OR ")))
+}
+
+/// Leaves out everything but letters and digits, which JQL and its text search would
+/// otherwise read as syntax.
+fn summary_filter(query: &str) -> Option<String> {
+    let word: String = query
+        .chars()
+        .filter(|character| character.is_alphanumeric())
+        .collect();
+    (!word.is_empty()).then(|| format!("summary ~ \"{word}*\""))
+}
+
+/// Limits the JQL to the given projects, or leaves it be when there are none.
+pub fn jql_in_projects(jql: &str, projects: &[String]) -> String {
+    if projects.is_empty() {
+        return jql.to_owned();
+    }
+    let quoted_projects: Vec<String> = projects
+        .iter()
+        .map(|project| format!("\"{project}\""))
+        .collect();
+    format!("project in ({}) AND ({jql})", quoted_projects.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn jql_is_limited_to_projects() {
+        let jql = jql_in_projects(
+            "assignee = currentUser() OR reporter = currentUser()",
+            &["AUTO".to_owned(), "OPS".to_owned()],
+        );
+
+        assert_eq!(
+            jql,
+            r#"project in ("AUTO", "OPS") AND (assignee = currentUser() OR reporter = currentUser())"#
+        );
+    }
+
+    #[test]
+    fn project_searches_match_the_start_of_keys_or_summary_words() {
+        assert_eq!(
+            project_search_jql("AUTO", "12"),
+            r#"project = "AUTO" AND (key = "AUTO-12" OR (key >= "AUTO-120" AND key <= "AUTO-129") OR (key >= "AUTO-1200" AND key <= "AUTO-1299") OR (key >= "AUTO-12000" AND key <= "AUTO-12999") OR (key >= "AUTO-120000" AND key <= "AUTO-129999")) ORDER BY updated DESC"#
+        );
+        assert_eq!(
+            project_search_jql("AUTO", "log_in"),
+            r#"project = "AUTO" AND summary ~ "login*" ORDER BY updated DESC"#
+        );
+        assert_eq!(
+            project_search_jql("AUTO", ""),
+            r#"project = "AUTO" ORDER BY updated DESC"#
+        );
+    }
+
+    /// AUTO-12, "Fix login bug" and in progress unless `fields` has a summary or status.
+    fn issue_with_fields(mut fields: serde_json::Value) -> Issue {
+        let fields_object = fields.as_object_mut().unwrap();
+        fields_object
+            .entry("summary")
+            .or_insert(json!("Fix login bug"));
+        fields_object.entry("status").or_insert(json!({
+            "name": "In Progress",
+            "statusCategory": { "key": "indeterminate" }
+        }));
+        serde_json::from_value(json!({
+            "self": "https://example.atlassian.net/rest/api/2/issue/10001",
+            "key": "AUTO-12",
+            "id": "10001",
+            "fields": fields
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn tickets_without_a_status_are_not_read() {
+        assert!(JiraTicket::try_from(issue_with_fields(json!({ "status": null }))).is_err());
+        assert!(JiraTicket::try_from(issue_with_fields(json!({ "status": {} }))).is_err());
+    }
+
+    #[test]
+    fn tickets_are_read_from_issue_fields() {
+        let issue = issue_with_fields(json!({
+            "assignee": {
+                "active": true,
+                "displayName": "Ada Lovelace",
+                "emailAddress": "ada@example.com",
+                "self": "https://example.atlassian.net/rest/api/2/user?accountId=1"
+            },
+            "priority": { "name": "High" },
+            "issuetype": { "name": "Bug", "subtask": false },
+            "created": "2024-11-02T09:15:00.000+0000",
+            "duedate": "2025-01-31",
+            "labels": ["auth", "web"],
+            "components": [{ "name": "Frontend" }],
+            "fixVersions": []
+        }));
+
+        let ticket = JiraTicket::try_from(issue).unwrap();
+
+        assert_eq!(ticket.assignee.as_deref(), Some("Ada Lovelace"));
+        assert_eq!(ticket.assignee_email.as_deref(), Some("ada@example.com"));
+        assert_eq!(ticket.reporter, None);
+        assert_eq!(ticket.priority.as_deref(), Some("High"));
+        assert_eq!(ticket.issue_type.as_deref(), Some("Bug"));
+        assert_eq!(ticket.created.as_deref(), Some("2024-11-02"));
+        assert_eq!(ticket.updated, None);
+        assert_eq!(ticket.due.as_deref(), Some("2025-01-31"));
+        assert_eq!(ticket.labels, vec!["auth", "web"]);
+        assert_eq!(ticket.components, vec!["Frontend"]);
+        assert!(ticket.fix_versions.is_empty());
+    }
+
+    #[test]
+    fn custom_fields_are_read_as_configured() {
+        let issue = issue_with_fields(json!({
+            "customfield_10016": 5.0,
+            "customfield_10020": [{ "id": 7, "name": "Sprint 7" }, { "id": 8, "name": "Sprint 8" }],
+            "customfield_10030": { "value": "Platform" },
+            "customfield_10040": { "displayName": "Grace Hopper" },
+            "customfield_10050": null
+        }));
+        let custom_field = |name: &str, field_type| CustomFieldConfig {
+            name: name.to_owned(),
+            field_type,
+        };
+        let custom_fields = BTreeMap::from([
+            (
+                "customfield_10016".to_owned(),
+                custom_field("Story points", CustomFieldType::Number),
+            ),
+            (
+                "customfield_10020".to_owned(),
+                custom_field("Sprint", CustomFieldType::OptionList),
+            ),
+            (
+                "customfield_10030".to_owned(),
+                custom_field("Team", CustomFieldType::OptionList),
+            ),
+            (
+                "customfield_10040".to_owned(),
+                custom_field("Tester", CustomFieldType::User),
+            ),
+            (
+                "customfield_10050".to_owned(),
+                custom_field("Acceptance criteria", CustomFieldType::Markup),
+            ),
+        ]);
+
+        let ticket = ticket_from_issue(issue, &custom_fields).unwrap();
+
+        assert_eq!(
+            ticket.details(),
+            "- **Story points:** 5\n- **Sprint:** Sprint 7, Sprint 8\n- **Team:** Platform\n- **Tester:** Grace Hopper\n"
+        );
+    }
+
+    #[test]
+    fn links_parent_and_subtasks_are_read_with_their_relation() {
+        let linked = |key: &str, status: &str, category: &str| {
+            json!({
+                "key": key,
+                "fields": {
+                    "summary": format!("Summary of {key}"),
+                    "status": { "name": status, "statusCategory": { "key": category } },
+                    "issuetype": { "name": if key == "AUTO-1" { "Epic" } else { "Task" } }
                 }
             })
-            .collect()
+        };
+        let issue = issue_with_fields(json!({
+            "parent": linked("AUTO-1", "In Progress", "indeterminate"),
+            "issuelinks": [
+                {
+                    "type": { "name": "Blocks", "inward": "is blocked by", "outward": "blocks" },
+                    "outwardIssue": linked("AUTO-20", "To Do", "new")
+                },
+                {
+                    "type": { "name": "Blocks", "inward": "is blocked by", "outward": "blocks" },
+                    "inwardIssue": linked("OPS-3", "Done", "done")
+                }
+            ],
+            "subtasks": [linked("AUTO-13", "Done", "done")]
+        }));
+
+        let ticket = JiraTicket::try_from(issue).unwrap();
+
+        let parent = ticket.parent.unwrap();
+        assert_eq!(
+            (parent.relation.as_str(), parent.key.as_str()),
+            ("parent", "AUTO-1")
+        );
+        assert!(parent.is_epic());
+        let links: Vec<(&str, &str, &str)> = ticket
+            .links
+            .iter()
+            .map(|link| {
+                (
+                    link.relation.as_str(),
+                    link.key.as_str(),
+                    link.status.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                ("blocks", "AUTO-20", "To Do"),
+                ("is blocked by", "OPS-3", "Done")
+            ]
+        );
+        assert_eq!(ticket.subtasks[0].title, "Summary of AUTO-13");
+        assert_eq!(ticket.subtasks[0].status_category, "done");
+    }
+
+    #[test]
+    fn comments_are_read_with_their_timestamp() {
+        let comment = comment_from_json(&json!({
+            "author": { "displayName": "Ada Lovelace" },
+            "body": "Fixed in *main*",
+            "created": "1970-01-01T01:00:30.000+0100"
+        }));
+
+        assert_eq!(
+            comment,
+            TicketComment {
+                author: "Ada Lovelace".to_owned(),
+                created: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(30)),
+                body: "Fixed in *main*".to_owned(),
+            }
+        );
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn details_leave_out_missing_values() {
+        let ticket = JiraTicket {
+            priority: Some("High".to_owned()),
+            labels: vec!["auth".to_owned(), "web".to_owned()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            ticket.details(),
+            "- **Priority:** High\n- **Labels:** auth, web\n"
+        );
+    }
+
+    #[test]
+    fn updates_are_asked_for_relative_to_now() {
+        let jql = updated_since_jql(SystemTime::now() - Duration::from_secs(90 * 60));
+
+        assert_eq!(jql, r#"updated >= "-91m""#);
+    }
+
+    #[test]
+    fn failing_responses_are_faults() {
+        let result = read_response::<serde_json::Value>(
+            StatusCode::SERVICE_UNAVAILABLE,
+            String::from("<html>Down for maintenance</html>"),
+        );
+
+        match result {
+            Err(Error::Fault { code, errors }) => {
+                assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+                assert_eq!(errors.error_messages, ["<html>Down for maintenance</html>"]);
+            }
+            other => panic!("expected a fault, got {other:?}"),
+        }
+        assert!(matches!(
+            read_response::<serde_json::Value>(StatusCode::NO_CONTENT, String::new()),
+            Ok(serde_json::Value::Null)
+        ));
+    }
+
+    #[test]
+    fn tickets_are_assigned_to_me_by_account_id_once_known() {
+        let jira_config: JiraConfig = toml::from_str(
+            r#"
+            host = "https://example.atlassian.net"
+            email = "me@example.com"
+            api_token = "token"
+            "#,
+        )
+        .unwrap();
+        let jira_resolver = JiraResolver::new(&jira_config, &NetworkConfig::default()).unwrap();
+        let ticket = |assignee_id: &str| JiraTicket {
+            assignee_id: Some(assignee_id.to_owned()),
+            assignee_email: None,
+            ..Default::default()
+        };
+        let mine_by_email = JiraTicket {
+            assignee_email: Some("Me@Example.com".to_owned()),
+            ..Default::default()
+        };
+
+        assert!(!jira_resolver.is_assigned_to_me(&ticket("5b10ac8d82e05b22cc7d4ef5")));
+        assert!(jira_resolver.is_assigned_to_me(&mine_by_email));
+
+        jira_resolver
+            .myself
+            .set(json!({ "accountId": "5b10ac8d82e05b22cc7d4ef5" }))
+            .unwrap();
+
+        assert!(jira_resolver.is_assigned_to_me(&ticket("5b10ac8d82e05b22cc7d4ef5")));
+        assert!(!jira_resolver.is_assigned_to_me(&ticket("5b10a2844c20165700ede21g")));
+    }
+
+    #[test]
+    fn jira_asks_to_wait_in_seconds() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, "12".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(12)));
+
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers), None);
     }
 }
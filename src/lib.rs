@@ -1,78 +1,634 @@
-use config::Config;
-use jira_resolver::JiraResolver;
-use log::{info, trace};
+use atlassian_markup_transpiler::transpile_atlassian_markup_to_markdown;
+use config::{
+    ticket_cache_file, Config, DiagnosticsConfig, JiraConfig, OtherProjects, RefrenceDisplay,
+    TemplatesConfig,
+};
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use document_store::DocumentStore;
+use fuzzy_matcher::fuzzy_score;
+use jira_resolver::{browse_url, jql_in_projects, JiraResolver, JiraTicket, TicketLookup};
+use log::{info, trace, warn};
 use lsp_types::{
-    request::DocumentLinkRequest, request::GotoDefinition, request::HoverRequest,
-    request::InlayHintRequest, request::Request, DocumentLink, DocumentLinkParams,
-    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
-    InitializeParams, InlayHint, InlayHintLabel, InlayHintParams, Location, MarkupContent,
-    MarkupKind, Position, Range, Uri,
+    notification::Cancel, notification::DidChangeTextDocument, notification::DidCloseTextDocument,
+    notification::DidOpenTextDocument, notification::LogMessage, notification::Notification,
+    notification::PublishDiagnostics, notification::ShowMessage, request::CodeActionRequest,
+    request::CodeLensRefresh, request::CodeLensRequest, request::Completion,
+    request::DocumentLinkRequest, request::DocumentLinkResolve, request::ExecuteCommand,
+    request::GotoDefinition, request::HoverRequest, request::InlayHintRefreshRequest,
+    request::InlayHintRequest, request::References, request::Request,
+    request::SemanticTokensFullRequest, request::SemanticTokensRangeRequest,
+    request::SemanticTokensRefresh, request::TypeHierarchyPrepare, request::TypeHierarchySubtypes,
+    request::TypeHierarchySupertypes, request::WorkspaceSymbolRequest, CancelParams, CodeAction,
+    CodeActionOrCommand, CodeActionParams, CodeLens, CodeLensParams, CompletionItem,
+    CompletionItemKind, CompletionItemLabelDetails, CompletionList, CompletionParams,
+    CompletionResponse, CompletionTextEdit, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentLink, DocumentLinkParams,
+    Documentation, ExecuteCommandParams, GotoDefinitionParams, GotoDefinitionResponse, Hover,
+    HoverContents, HoverParams, InitializeParams, InlayHint, InlayHintLabel, InlayHintParams,
+    InlayHintTooltip, Location, LogMessageParams, MarkupContent, MarkupKind, MessageType,
+    NumberOrString, OneOf, Position, PublishDiagnosticsParams, Range, ReferenceParams,
+    SemanticTokens, SemanticTokensParams, SemanticTokensRangeParams, SemanticTokensRangeResult,
+    SemanticTokensResult, ShowMessageParams, SymbolKind, TextEdit, TypeHierarchyItem,
+    TypeHierarchyPrepareParams, TypeHierarchySubtypesParams, TypeHierarchySupertypesParams, Uri,
+    WorkspaceSymbol, WorkspaceSymbolParams, WorkspaceSymbolResponse,
 };
-use refrence_finder::{InFileRefrenceType, RefrenceFinder};
+use progress_reporter::ProgressReporter;
+use refrence_finder::{InFileRefrence, InFileRefrenceType, RefrenceFinder};
+pub use semantic_tokens::semantic_tokens_legend;
+use semantic_tokens::{encode_ticket_tokens, ticket_token_modifiers};
 use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::panic::{self, AssertUnwindSafe};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::thread;
+use std::time::{Duration, SystemTime};
+pub use ticket_commands::TICKET_COMMANDS;
+use ticket_commands::{client_shows_references, show_refrences_command, TicketCommand};
+use ticket_diagnostics::diagnostic_for_refrence;
+pub use ticket_document::JIRA_SCHEME;
+use ticket_document::{
+    render_ticket_document, ticket_document_uri, ticket_key_from_document_uri,
+    TextDocumentContentParams, TextDocumentContentRequest, TextDocumentContentResult,
+};
+use ticket_hierarchy::{linked_type_hierarchy_item, ticket_key_from_item, type_hierarchy_item};
+use ticket_store::TicketStore;
+use ticket_template::TemplateContext;
+use worker_pool::WorkerPool;
+use workspace_scanner::{read_file, WorkspaceScanner};
 
 use lsp_server;
-use lsp_server::{Connection, Message, RequestId, Response};
+use lsp_server::{Connection, ErrorCode, Message, RequestId, Response, ResponseError};
+
+/// The most tickets a `workspace/symbol` search returns.
+const WORKSPACE_SYMBOL_LIMIT: usize = 100;
+
+/// How many requests are handled at once. Most of their time is spent waiting on Jira.
+const WORKER_COUNT: usize = 4;
 
 mod atlassian_markup_transpiler;
 pub mod config;
+mod document_store;
+mod fuzzy_matcher;
 mod jira_resolver;
+mod progress_reporter;
 mod refrence_finder;
+mod request_guard;
+mod semantic_tokens;
+mod ticket_commands;
+mod ticket_diagnostics;
+mod ticket_document;
+mod ticket_hierarchy;
+mod ticket_store;
+mod ticket_template;
+mod worker_pool;
+mod workspace_scanner;
 
+/// Shared between the message loop and the workers handling requests.
+///
+/// Only the message loop writes to the document store and refrence finder, so that
+/// edits are applied in the order they were sent. Workers must not hold either lock
+/// while waiting on Jira, and take the document store lock first when needing both.
+/// The ticket store is never locked while the refrence finder is, or the other way
+/// around, as writers waiting on either could otherwise deadlock the two.
 pub struct Server {
     connection: Connection,
     params: InitializeParams,
-    refrence_finder: RefrenceFinder,
+    workspace_scanner: WorkspaceScanner,
+    document_store: RwLock<DocumentStore>,
+    refrence_finder: RwLock<RefrenceFinder>,
     jira_resolver: JiraResolver,
+    jira_config: JiraConfig,
+    diagnostics_config: DiagnosticsConfig,
+    refrence_display: RefrenceDisplay,
+    templates: TemplatesConfig,
+    sync_interval: Option<Duration>,
+    hover_comment_count: usize,
+    worker_pool: WorkerPool,
+    /// Requests handed to the workers that have not been answered yet, and whether the
+    /// client has since cancelled them.
+    pending_requests: Mutex<HashMap<RequestId, bool>>,
+    /// Documents waiting on a worker to publish their diagnostics, so that a burst of
+    /// edits queues one job rather than one per edit.
+    queued_diagnostics: Mutex<HashSet<Uri>>,
+    ticket_store: RwLock<TicketStore>,
+    next_request_id: AtomicI32,
+    next_progress_token: AtomicI32,
 }
 
 impl Server {
-    pub fn new(connection: Connection, params: InitializeParams, config: &Config) -> Server {
-        Server {
+    /// Hands the connection back when Jira can not be reached through the config, so that
+    /// the user can still be told why.
+    pub fn new(
+        connection: Connection,
+        params: InitializeParams,
+        config: &Config,
+    ) -> Result<Server, (Connection, gouqi::Error)> {
+        let jira_resolver = match JiraResolver::new(&config.jira, &config.network) {
+            Ok(jira_resolver) => jira_resolver,
+            Err(error) => return Err((connection, error)),
+        };
+        Ok(Server {
             connection,
+            workspace_scanner: WorkspaceScanner::new(&params),
             params,
-            refrence_finder: RefrenceFinder::new(),
-            jira_resolver: JiraResolver::new(&config.jira),
-        }
+            document_store: RwLock::new(DocumentStore::new()),
+            refrence_finder: RwLock::new(RefrenceFinder::new()),
+            jira_resolver,
+            jira_config: config.jira.to_owned(),
+            diagnostics_config: config.diagnostics.to_owned(),
+            refrence_display: config.refrence_display,
+            templates: config.templates.to_owned(),
+            sync_interval: config.cache.sync_interval(),
+            hover_comment_count: config.hover.comment_count,
+            worker_pool: WorkerPool::new(WORKER_COUNT),
+            pending_requests: Mutex::new(HashMap::new()),
+            queued_diagnostics: Mutex::new(HashSet::new()),
+            ticket_store: RwLock::new(TicketStore::load(
+                config.cache.ttl(),
+                ticket_cache_file(&config.jira.host),
+            )),
+            next_request_id: AtomicI32::new(0),
+            next_progress_token: AtomicI32::new(0),
+        })
     }
-    pub fn run_loop(&mut self) -> Result<(), Box<dyn Error + Sync + Send>> {
+    pub fn run_loop(self) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let server = Arc::new(self);
+        let worker_server = Arc::clone(&server);
+        server.worker_pool.execute(move || {
+            worker_server.scan_workspace();
+            worker_server.load_workspace_tickets();
+        });
+        // Dropped when the loop ends, which stops the syncing, so that the server and with it
+        // the connection are not kept alive after `exit`.
+        let (_stop_syncing, stopped_syncing) = crossbeam_channel::bounded::<()>(0);
+        if let Some(sync_interval) = server.sync_interval {
+            let syncing_server = Arc::clone(&server);
+            thread::Builder::new()
+                .name(String::from("ticket-sync"))
+                .spawn(move || syncing_server.sync_tickets_every(sync_interval, stopped_syncing))?;
+        }
         loop {
-            let msg = self.connection.receiver.recv()?;
+            let msg = server.connection.receiver.recv()?;
             trace!("got msg: {msg:?}");
             match msg {
                 Message::Request(request) => {
-                    if self.connection.handle_shutdown(&request)? {
+                    if server.connection.handle_shutdown(&request)? {
                         return Ok(());
                     }
-                    self.handle_request(request)?
+                    Server::handle_request_in_background(&server, request, Server::handle_request);
                 }
                 Message::Response(resp) => {
                     info!("got response: {resp:?}");
                 }
-                Message::Notification(notification) => self.handle_notification(notification)?,
+                Message::Notification(notification) => {
+                    if let Err(e) = server.handle_notification(notification) {
+                        server.log_message(MessageType::ERROR, e.to_string());
+                    }
+                }
             }
         }
     }
 
+    /// Finds the refrences in every file of the workspace, so that they can be found
+    /// without the file being open. The refrence finder is only locked a file at a time,
+    /// so that documents opened meanwhile are not held up.
+    fn scan_workspace(&self) {
+        let mut progress_reporter = ProgressReporter::begin(self, "Scanning workspace");
+        let files = self.workspace_scanner.files();
+        for (scanned_files, path) in files.iter().enumerate() {
+            if let Some((uri, contents)) = read_file(path) {
+                let document_store = self.document_store.read().unwrap();
+                // What the editor has open is newer than what is on disk.
+                if document_store.get(&uri).is_none() {
+                    self.refrence_finder
+                        .write()
+                        .unwrap()
+                        .scan_document(&uri, &contents);
+                }
+            }
+            progress_reporter.report(scanned_files + 1, files.len(), || {
+                format!("{}/{} files", scanned_files + 1, files.len())
+            });
+        }
+        info!("Scanned {} workspace files", files.len());
+        progress_reporter.end(format!("Scanned {} files", files.len()));
+    }
+
+    /// Fetches the tickets the prefetch JQL matches and those of the configured projects
+    /// refrenced anywhere in the workspace, so that they are at hand by the time the user
+    /// looks at them.
+    fn load_workspace_tickets(&self) {
+        // Tells the tickets assigned to the user apart.
+        if let Err(e) = self.jira_resolver.fetch_myself() {
+            warn!("Could not fetch the Jira user because {e:?}");
+        }
+        self.sync_tickets();
+        if let Some(prefetch_jql) = &self.jira_config.prefetch_jql {
+            let mut progress_reporter = ProgressReporter::begin(self, "Prefetching Jira tickets");
+            let result = self.jira_resolver.search_tickets(
+                &jql_in_projects(prefetch_jql, &self.jira_config.projects),
+                |fetched, total| {
+                    progress_reporter
+                        .report(fetched, total, || format!("{fetched}/{total} tickets"))
+                },
+            );
+            match result {
+                Ok(tickets) => {
+                    progress_reporter.end(format!("Prefetched {} tickets", tickets.len()));
+                    self.ticket_store
+                        .write()
+                        .unwrap()
+                        .extend(tickets.into_iter().map(|ticket| {
+                            (ticket.key.to_owned(), TicketLookup::Found(Box::new(ticket)))
+                        }));
+                }
+                Err(e) => {
+                    warn!("Could not prefetch tickets because {e:?}");
+                    progress_reporter.end(String::from("Could not reach Jira"));
+                    // Most likely a mistake in the JQL, which only the user can fix.
+                    self.show_message(
+                        MessageType::WARNING,
+                        format!("Could not prefetch Jira tickets: {e}"),
+                    );
+                }
+            }
+        }
+
+        let refrenced_keys: Vec<String> = self
+            .refrence_finder
+            .read()
+            .unwrap()
+            .ticket_keys()
+            .into_iter()
+            .filter(|key| self.jira_config.is_configured_project(key))
+            .map(|key| key.to_owned())
+            .collect();
+        let keys_to_fetch = self
+            .ticket_store
+            .read()
+            .unwrap()
+            .keys_to_fetch(refrenced_keys.iter().map(String::as_str));
+        let mut progress_reporter = ProgressReporter::begin(self, "Loading Jira tickets");
+        let lookups = self
+            .jira_resolver
+            .fetch_tickets(&keys_to_fetch, |fetched, total| {
+                progress_reporter.report(fetched, total, || format!("{fetched}/{total} tickets"))
+            });
+        progress_reporter.end(format!("Loaded {} tickets", lookups.len()));
+        let mut ticket_store = self.ticket_store.write().unwrap();
+        ticket_store.extend(lookups);
+        ticket_store.save();
+        drop(ticket_store);
+        self.refresh_ticket_views();
+    }
+
+    /// Keeps the tickets up to date while the server runs, so that changes made by others
+    /// show up without the user having to do anything. Stops once the sending half of
+    /// `stopped` is dropped.
+    fn sync_tickets_every(&self, sync_interval: Duration, stopped: Receiver<()>) {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(sync_interval) {
+            let updated_keys = self.sync_tickets();
+            if updated_keys.is_empty() {
+                continue;
+            }
+            let shown_keys: HashSet<String> = {
+                let document_store = self.document_store.read().unwrap();
+                let refrence_finder = self.refrence_finder.read().unwrap();
+                document_store
+                    .uris()
+                    .flat_map(|uri| refrence_finder.get_refrences(uri))
+                    .filter_map(|refrence| jira_ticket_key(refrence).map(str::to_owned))
+                    .collect()
+            };
+            if updated_keys.iter().any(|key| shown_keys.contains(key)) {
+                self.refresh_ticket_views();
+            }
+        }
+    }
+
+    /// Fetches only the tracked tickets that changed since they were last fetched or synced,
+    /// rather than all of them, returning the keys of those that changed.
+    fn sync_tickets(&self) -> Vec<String> {
+        let (last_synced_at, tracked_keys) = {
+            let ticket_store = self.ticket_store.read().unwrap();
+            (ticket_store.last_synced_at(), ticket_store.tracked_keys())
+        };
+        let Some(last_synced_at) = last_synced_at else {
+            return Vec::new();
+        };
+        // Tickets of the configured projects are all found by asking for the projects.
+        let keys_outside_projects: Vec<String> = if self.jira_config.projects.is_empty() {
+            tracked_keys
+        } else {
+            tracked_keys
+                .into_iter()
+                .filter(|key| !self.jira_config.is_configured_project(key))
+                .collect()
+        };
+        let synced_at = SystemTime::now();
+        let updated_tickets = match self.jira_resolver.fetch_updated_tickets(
+            &self.jira_config.projects,
+            &keys_outside_projects,
+            last_synced_at,
+        ) {
+            Ok(updated_tickets) => updated_tickets,
+            Err(e) => {
+                warn!("Could not sync tickets because {e:?}");
+                self.log_message(
+                    MessageType::WARNING,
+                    format!("Could not sync Jira tickets, showing cached ones: {e}"),
+                );
+                return Vec::new();
+            }
+        };
+        info!("Synced {} changed tickets", updated_tickets.len());
+        let updated_keys = updated_tickets
+            .iter()
+            .map(|ticket| ticket.key.to_owned())
+            .collect();
+        let mut ticket_store = self.ticket_store.write().unwrap();
+        ticket_store.apply_sync(synced_at, updated_tickets);
+        ticket_store.save();
+        updated_keys
+    }
+
+    /// Fetches whichever of the tickets have not been looked up yet, or not recently, into
+    /// the ticket store.
+    fn load_tickets<'a>(&self, keys: impl IntoIterator<Item = &'a str>) {
+        let keys_to_fetch = self.ticket_store.read().unwrap().keys_to_fetch(
+            keys.into_iter()
+                .filter(|key| self.is_fetched_on_demand(key)),
+        );
+        if keys_to_fetch.is_empty() {
+            return;
+        }
+        let lookups = self.jira_resolver.fetch_tickets(&keys_to_fetch, |_, _| ());
+        if lookups.is_empty() {
+            // Jira could not be reached, so whatever is stored stays, marked as stale.
+            return;
+        }
+        let mut ticket_store = self.ticket_store.write().unwrap();
+        ticket_store.extend(lookups);
+        ticket_store.save();
+    }
+
+    /// Whether a refrence to the ticket is looked up in Jira at all.
+    fn is_fetched_on_demand(&self, key: &str) -> bool {
+        self.jira_config.is_configured_project(key)
+            || self.jira_config.other_projects == OtherProjects::FetchOnDemand
+    }
+
     fn handle_notification(
-        &self,
+        self: &Arc<Self>,
         notification: lsp_server::Notification,
     ) -> Result<(), Box<dyn Error + Sync + Send>> {
-        // lsp_notification!(notification.method.as_str());
         match notification.method.as_str() {
-            // notification::
+            DidOpenTextDocument::METHOD => {
+                let params = cast_notification::<DidOpenTextDocument>(notification)?;
+                self.process_did_open(params);
+            }
+            DidChangeTextDocument::METHOD => {
+                let params = cast_notification::<DidChangeTextDocument>(notification)?;
+                self.process_did_change(params);
+            }
+            DidCloseTextDocument::METHOD => {
+                let params = cast_notification::<DidCloseTextDocument>(notification)?;
+                self.process_did_close(params);
+            }
+            Cancel::METHOD => {
+                let params = cast_notification::<Cancel>(notification)?;
+                self.process_cancel(params);
+            }
             _ => info!("got notification: {notification:?}"),
         }
         Ok(())
     }
 
-    fn handle_request(
-        &mut self,
+    /// Work already started is not interrupted, but its result is replaced by
+    /// `RequestCancelled`. Handlers making several calls to Jira, such as hovers and code
+    /// actions, check for cancellation between them.
+    fn process_cancel(&self, cancel_params: CancelParams) {
+        let request_id = match cancel_params.id {
+            NumberOrString::Number(id) => RequestId::from(id),
+            NumberOrString::String(id) => RequestId::from(id),
+        };
+        if let Some(cancelled) = self.pending_requests.lock().unwrap().get_mut(&request_id) {
+            *cancelled = true;
+        }
+    }
+
+    fn is_cancelled(&self, request_id: &RequestId) -> bool {
+        self.pending_requests
+            .lock()
+            .unwrap()
+            .get(request_id)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    fn process_did_open(self: &Arc<Self>, did_open_params: DidOpenTextDocumentParams) {
+        let text_document = did_open_params.text_document;
+        {
+            let mut document_store = self.document_store.write().unwrap();
+            let document = document_store.open(
+                text_document.uri.clone(),
+                text_document.version,
+                text_document.text,
+            );
+            self.refrence_finder
+                .write()
+                .unwrap()
+                .scan_document(&text_document.uri, &document.text);
+        }
+        self.publish_diagnostics_in_background(text_document.uri);
+    }
+
+    fn process_did_change(self: &Arc<Self>, did_change_params: DidChangeTextDocumentParams) {
+        let uri = did_change_params.text_document.uri;
+        {
+            let mut document_store = self.document_store.write().unwrap();
+            let Some(document) = document_store.change(
+                &uri,
+                did_change_params.text_document.version,
+                did_change_params.content_changes,
+            ) else {
+                warn!("Got changes for {} which was never opened", uri.as_str());
+                return;
+            };
+            self.refrence_finder
+                .write()
+                .unwrap()
+                .scan_document(&uri, &document.text);
+        }
+        self.publish_diagnostics_in_background(uri);
+    }
+
+    fn process_did_close(&self, did_close_params: DidCloseTextDocumentParams) {
+        let uri = did_close_params.text_document.uri;
+        self.document_store.write().unwrap().close(&uri);
+        // Workspace files stay searchable, as they are on disk rather than in the editor.
+        let contents = self.workspace_scanner.read(&uri);
+        let mut refrence_finder = self.refrence_finder.write().unwrap();
+        match contents {
+            Some(contents) => refrence_finder.scan_document(&uri, &contents),
+            None => refrence_finder.forget_document(&uri),
+        }
+        drop(refrence_finder);
+        self.send_notification::<PublishDiagnostics>(PublishDiagnosticsParams::new(
+            uri,
+            Vec::new(),
+            None,
+        ));
+    }
+
+    fn publish_diagnostics_in_background(self: &Arc<Self>, uri: Uri) {
+        if !self.queued_diagnostics.lock().unwrap().insert(uri.clone()) {
+            // The queued job reads the document as it is by the time it runs.
+            return;
+        }
+        let worker_server = Arc::clone(self);
+        self.worker_pool.execute(move || {
+            worker_server
+                .queued_diagnostics
+                .lock()
+                .unwrap()
+                .remove(&uri);
+            worker_server.publish_diagnostics(&uri);
+        });
+    }
+
+    /// Flags refrences to tickets that are done or that Jira does not know about. Nothing
+    /// is published if the document changed or closed while Jira was asked, as newer
+    /// diagnostics would be replaced by older ones.
+    fn publish_diagnostics(&self, uri: &Uri) {
+        let (version, refrences) = {
+            let document_store = self.document_store.read().unwrap();
+            let Some(document) = document_store.get(uri) else {
+                return;
+            };
+            (document.version, self.refrences_in(uri))
+        };
+        self.load_tickets(refrences.iter().filter_map(jira_ticket_key));
+        let diagnostics = {
+            let ticket_store = self.ticket_store.read().unwrap();
+            refrences
+                .iter()
+                .filter_map(|refrence| {
+                    let ticket = jira_ticket_key(refrence)?;
+                    diagnostic_for_refrence(
+                        &self.diagnostics_config,
+                        &self.jira_config,
+                        ticket,
+                        refrence.range.to_owned().into(),
+                        ticket_store.get(ticket)?,
+                    )
+                })
+                .collect()
+        };
+        // Held while publishing, so that the document can not close in between.
+        let document_store = self.document_store.read().unwrap();
+        if document_store
+            .get(uri)
+            .is_none_or(|document| document.version != version)
+        {
+            return;
+        }
+        self.send_notification::<PublishDiagnostics>(PublishDiagnosticsParams::new(
+            uri.to_owned(),
+            diagnostics,
+            Some(version),
+        ));
+    }
+
+    /// Copies out the refrences in a document, so that no lock is held while asking Jira
+    /// about them.
+    fn refrences_in(&self, uri: &Uri) -> Vec<InFileRefrence> {
+        self.refrence_finder
+            .read()
+            .unwrap()
+            .get_refrences(uri)
+            .cloned()
+            .collect()
+    }
+
+    /// Copies out the refrences in the document and fetches their tickets, then hands out
+    /// the ticket store to read them from.
+    fn load_document_tickets(
+        &self,
+        uri: &Uri,
+    ) -> (Vec<InFileRefrence>, RwLockReadGuard<'_, TicketStore>) {
+        let refrences = self.refrences_in(uri);
+        self.load_tickets(refrences.iter().filter_map(jira_ticket_key));
+        (refrences, self.ticket_store.read().unwrap())
+    }
+
+    fn refrence_locations(&self, ticket: &str) -> Vec<Location> {
+        ticket_refrence_locations(&self.refrence_finder.read().unwrap(), ticket)
+    }
+
+    fn template_context<'a>(
+        &'a self,
+        ticket: &'a JiraTicket,
+        stale_for: Option<Duration>,
+    ) -> TemplateContext<'a> {
+        TemplateContext {
+            ticket,
+            jira_host: self.jira_resolver.host(),
+            stale_for,
+            icon_set: self.templates.icons,
+        }
+    }
+
+    /// Handles the request on a worker with `handle`. The request is still answered when
+    /// `handle` panics, so that the client is not left waiting.
+    fn handle_request_in_background(
+        server: &Arc<Server>,
         request: lsp_server::Request,
-    ) -> Result<(), Box<dyn Error + Sync + Send>> {
+        handle: fn(&Server, lsp_server::Request),
+    ) {
+        server
+            .pending_requests
+            .lock()
+            .unwrap()
+            .insert(request.id.to_owned(), false);
+        let worker_server = Arc::clone(server);
+        server.worker_pool.execute(move || {
+            let request_id = request.id.to_owned();
+            let handled = panic::catch_unwind(AssertUnwindSafe(|| handle(&worker_server, request)));
+            if handled.is_err() {
+                worker_server.send_error_response(
+                    &request_id,
+                    ErrorCode::InternalError,
+                    String::from("The server failed while handling the request"),
+                );
+            }
+        });
+    }
+
+    fn handle_request(&self, request: lsp_server::Request) {
         info!("got request: {request:?}");
+        if self.is_cancelled(&request.id) {
+            self.send_cancelled_response(&request.id);
+            return;
+        }
+        let request_id = request.id.to_owned();
+        if let Err(response_error) = self.dispatch_request(request) {
+            warn!(
+                "Could not handle request {request_id} because {}",
+                response_error.message
+            );
+            self.send_response_message(Response {
+                id: request_id,
+                result: None,
+                error: Some(response_error),
+            });
+        }
+    }
+
+    fn dispatch_request(&self, request: lsp_server::Request) -> Result<(), ResponseError> {
         match request.method.as_str() {
             GotoDefinition::METHOD => {
                 let (request_id, params) = cast::<GotoDefinition>(request)?;
@@ -90,7 +646,64 @@ impl Server {
                 let (request_id, params) = cast::<DocumentLinkRequest>(request)?;
                 self.process_document_link_request(&request_id, &params);
             }
-            method => panic!("Unknown request {method}"),
+            DocumentLinkResolve::METHOD => {
+                let (request_id, params) = cast::<DocumentLinkResolve>(request)?;
+                self.process_document_link_resolve_request(&request_id, params);
+            }
+            CodeActionRequest::METHOD => {
+                let (request_id, params) = cast::<CodeActionRequest>(request)?;
+                self.process_code_action_request(&request_id, &params);
+            }
+            ExecuteCommand::METHOD => {
+                let (request_id, params) = cast::<ExecuteCommand>(request)?;
+                self.process_execute_command_request(&request_id, params);
+            }
+            WorkspaceSymbolRequest::METHOD => {
+                let (request_id, params) = cast::<WorkspaceSymbolRequest>(request)?;
+                self.process_workspace_symbol_request(&request_id, &params);
+            }
+            CodeLensRequest::METHOD => {
+                let (request_id, params) = cast::<CodeLensRequest>(request)?;
+                self.process_code_lens_request(&request_id, &params);
+            }
+            References::METHOD => {
+                let (request_id, params) = cast::<References>(request)?;
+                self.process_references_request(&request_id, &params);
+            }
+            SemanticTokensFullRequest::METHOD => {
+                let (request_id, params) = cast::<SemanticTokensFullRequest>(request)?;
+                self.process_semantic_tokens_full_request(&request_id, &params);
+            }
+            SemanticTokensRangeRequest::METHOD => {
+                let (request_id, params) = cast::<SemanticTokensRangeRequest>(request)?;
+                self.process_semantic_tokens_range_request(&request_id, &params);
+            }
+            Completion::METHOD => {
+                let (request_id, params) = cast::<Completion>(request)?;
+                self.process_completion_request(&request_id, &params);
+            }
+            TextDocumentContentRequest::METHOD => {
+                let (request_id, params) = cast::<TextDocumentContentRequest>(request)?;
+                self.process_text_document_content_request(&request_id, &params);
+            }
+            TypeHierarchyPrepare::METHOD => {
+                let (request_id, params) = cast::<TypeHierarchyPrepare>(request)?;
+                self.process_prepare_type_hierarchy_request(&request_id, &params);
+            }
+            TypeHierarchySupertypes::METHOD => {
+                let (request_id, params) = cast::<TypeHierarchySupertypes>(request)?;
+                self.process_type_hierarchy_supertypes_request(&request_id, &params);
+            }
+            TypeHierarchySubtypes::METHOD => {
+                let (request_id, params) = cast::<TypeHierarchySubtypes>(request)?;
+                self.process_type_hierarchy_subtypes_request(&request_id, &params);
+            }
+            method => {
+                return Err(response_error(
+                    ErrorCode::MethodNotFound,
+                    format!("Unknown request {method}"),
+                ))
+            }
         }
         Ok(())
     }
@@ -100,52 +713,65 @@ impl Server {
         request_id: &RequestId,
         document_link_request_params: &DocumentLinkParams,
     ) {
-        return;
-        let response = DocumentLink {
-            range: Range {
-                start: Position {
-                    line: 7,
-                    character: 7,
-                },
-                end: Position {
-                    line: 7,
-                    character: 16,
-                },
-            },
-            target: Some(Uri::from_str("https://danjones.dev").unwrap()),
-            tooltip: Some(String::from("View in Jira")),
-            data: None,
-        };
-        self.send_response(request_id, &vec![response]);
+        // Targets and tooltips are filled in by `documentLink/resolve` so that listing the
+        // links of a document never has to wait on Jira.
+        let document_links: Vec<DocumentLink> = self
+            .refrence_finder
+            .read()
+            .unwrap()
+            .get_refrences(&document_link_request_params.text_document.uri)
+            .map(|refrence| DocumentLink {
+                range: refrence.range.to_owned().into(),
+                target: None,
+                tooltip: None,
+                data: serde_json::to_value(&refrence.marker).ok(),
+            })
+            .collect();
+        self.send_response(request_id, &document_links);
     }
 
-    fn process_hover_request(
-        &mut self,
+    fn process_document_link_resolve_request(
+        &self,
         request_id: &RequestId,
-        hover_request_params: &HoverParams,
+        mut document_link: DocumentLink,
     ) {
-        let hover_position = hover_request_params.text_document_position_params.position;
-        if hover_request_params
-            .text_document_position_params
-            .text_document
-            .uri
-            .scheme()
-            .map_or("", |x| x.as_str())
-            == "output"
-        {
-            return;
+        let marker = document_link
+            .data
+            .take()
+            .and_then(|data| serde_json::from_value::<InFileRefrenceType>(data).ok());
+        match marker {
+            Some(InFileRefrenceType::JiraRefrence { ticket }) => {
+                document_link.target =
+                    Uri::from_str(&browse_url(self.jira_resolver.host(), &ticket)).ok();
+                self.load_tickets([ticket.as_str()]);
+                document_link.tooltip = Some(
+                    self.ticket_store
+                        .read()
+                        .unwrap()
+                        .ticket(&ticket)
+                        .map_or_else(|| format!("View {ticket} in Jira"), |x| x.title.to_owned()),
+                );
+            }
+            Some(InFileRefrenceType::GitHubUrlRefrence { url })
+            | Some(InFileRefrenceType::GitLabUrlRefrence { url }) => {
+                document_link.target = Uri::from_str(&url).ok();
+            }
+            None => warn!("Can not resolve document link without a refrence"),
         }
-        let file_path = hover_request_params
-            .text_document_position_params
-            .text_document
-            .uri
-            .path()
-            .as_str();
+        self.send_response(request_id, &document_link);
+    }
+
+    fn process_hover_request(&self, request_id: &RequestId, hover_request_params: &HoverParams) {
+        let hover_position = hover_request_params.text_document_position_params.position;
         let refrence_at_position = self
-            .refrence_finder
-            .get_refrences(file_path)
-            .filter(|&refrence| refrence.range.contains_position(hover_position))
-            .next();
+            .refrences_in(
+                &hover_request_params
+                    .text_document_position_params
+                    .text_document
+                    .uri,
+            )
+            .into_iter()
+            .find(|refrence| refrence.range.contains_position(hover_position));
 
         if refrence_at_position.is_none() {
             self.send_empty_resonse(request_id);
@@ -153,18 +779,33 @@ impl Server {
         }
         let refrence_at_position = refrence_at_position.unwrap();
 
-        // TODO: proper jira intergration
-        let tickets_in_jira = self.jira_resolver.get_jira_tickets();
-        if let Some(ticket) = tickets_in_jira.get(match &refrence_at_position.marker {
-            InFileRefrenceType::JiraRefrence { ticket } => ticket.as_str(),
-            _ => "",
-        }) {
+        let Some(key) = jira_ticket_key(&refrence_at_position) else {
+            self.send_empty_resonse(request_id);
+            return;
+        };
+        self.load_tickets([key]);
+        if self.is_cancelled(request_id) {
+            self.send_cancelled_response(request_id);
+            return;
+        }
+        self.load_comments(key);
+        if self.is_cancelled(request_id) {
+            self.send_cancelled_response(request_id);
+            return;
+        }
+        self.load_child_progress(key);
+        let ticket_store = self.ticket_store.read().unwrap();
+        if let Some(ticket) = ticket_store.ticket(key) {
+            let value = self
+                .templates
+                .hover
+                .render(&self.template_context(ticket, ticket_store.stale_for(key)));
             let response = Hover {
                 contents: HoverContents::Markup(MarkupContent {
                     kind: MarkupKind::Markdown,
-                    value: ticket.to_string(),
+                    value,
                 }),
-                range: Some(refrence_at_position.range.to_owned().into()),
+                range: Some(refrence_at_position.range.into()),
             };
             self.send_response(request_id, &response);
             return;
@@ -172,42 +813,96 @@ impl Server {
         self.send_empty_resonse(request_id);
     }
 
+    /// Fetches the comments of a ticket the first time it is hovered, as they are only
+    /// shown there and there can be many of them.
+    fn load_comments(&self, key: &str) {
+        let comments_missing = self
+            .ticket_store
+            .read()
+            .unwrap()
+            .ticket(key)
+            .is_some_and(|ticket| ticket.comments.is_none());
+        if !comments_missing || self.hover_comment_count == 0 {
+            return;
+        }
+        match self
+            .jira_resolver
+            .fetch_comments(key, self.hover_comment_count)
+        {
+            Ok(comments) => {
+                let mut ticket_store = self.ticket_store.write().unwrap();
+                ticket_store.set_comments(key, comments);
+                ticket_store.save();
+            }
+            Err(e) => warn!("Could not fetch the comments of {key} because {e:?}"),
+        }
+    }
+
+    /// Counts the children of the ticket, or of its parent, when that is an epic. Done
+    /// when the ticket is hovered, as it takes a search per epic.
+    fn load_child_progress(&self, key: &str) {
+        let epic_keys: Vec<String> = {
+            let ticket_store = self.ticket_store.read().unwrap();
+            let Some(ticket) = ticket_store.ticket(key) else {
+                return;
+            };
+            let parent = ticket.parent.as_ref();
+            [
+                (ticket.is_epic() && ticket.child_progress.is_none()).then_some(&ticket.key),
+                parent
+                    .filter(|parent| parent.is_epic() && parent.child_progress.is_none())
+                    .map(|parent| &parent.key),
+            ]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+        };
+        for epic_key in epic_keys {
+            match self.jira_resolver.fetch_child_progress(&epic_key) {
+                Ok(progress) => {
+                    let mut ticket_store = self.ticket_store.write().unwrap();
+                    ticket_store.set_child_progress(key, &epic_key, progress);
+                    ticket_store.save();
+                }
+                Err(e) => warn!("Could not count the children of {epic_key} because {e:?}"),
+            }
+        }
+    }
+
     fn process_inlay_hint_request(
-        &mut self,
+        &self,
         request_id: &RequestId,
         inlay_hint_params: &InlayHintParams,
     ) {
-        let tickets_in_jira = self.jira_resolver.get_jira_tickets();
-        if inlay_hint_params
-            .text_document
-            .uri
-            .scheme()
-            .map_or("", |x| x.as_str())
-            == "output"
-        {
+        if !self.refrence_display.shows_inlay_hints() {
+            self.send_empty_resonse(request_id);
             return;
         }
-
-        let inlay_hints: Vec<InlayHint> = self
-            .refrence_finder
-            .get_refrences(inlay_hint_params.text_document.uri.path().as_str())
+        let (refrences, ticket_store) =
+            self.load_document_tickets(&inlay_hint_params.text_document.uri);
+        let inlay_hints: Vec<InlayHint> = refrences
+            .iter()
             .filter_map(|refrence| {
                 let position = refrence.range.end_position();
                 let ticket = match &refrence.marker {
                     InFileRefrenceType::JiraRefrence { ticket, .. } => ticket,
                     _ => "UNKNOWN",
                 };
-                tickets_in_jira.get(ticket).map(|jira_ticket| InlayHint {
+                let jira_ticket = ticket_store.ticket(ticket)?;
+                let template_context =
+                    self.template_context(jira_ticket, ticket_store.stale_for(ticket));
+                let tooltip = self.templates.tooltip.render(&template_context);
+                Some(InlayHint {
                     position: position.to_owned(),
-                    label: InlayHintLabel::String(format!(
-                        ": {} ({})",
-                        jira_ticket.title, jira_ticket.status,
-                    )),
+                    label: InlayHintLabel::String(
+                        self.templates.inlay_hint.render(&template_context),
+                    ),
                     padding_left: None,
                     padding_right: Some(true),
                     kind: None,
                     text_edits: None,
-                    tooltip: None,
+                    tooltip: (!tooltip.is_empty()).then_some(InlayHintTooltip::String(tooltip)),
                     data: None,
                 })
             })
@@ -218,6 +913,478 @@ impl Server {
         self.send_response(request_id, &result);
     }
 
+    fn process_completion_request(
+        &self,
+        request_id: &RequestId,
+        completion_params: &CompletionParams,
+    ) {
+        let text_document_position = &completion_params.text_document_position;
+        let line_before = self
+            .document_store
+            .read()
+            .unwrap()
+            .get(&text_document_position.text_document.uri)
+            .map(|document| {
+                document
+                    .line_before(text_document_position.position)
+                    .to_owned()
+            });
+        let partial_refrence = line_before.as_deref().and_then(|line_before| {
+            self.refrence_finder
+                .read()
+                .unwrap()
+                .find_partial_refrence(line_before)
+        });
+        let Some(partial_refrence) = partial_refrence else {
+            self.send_empty_resonse(request_id);
+            return;
+        };
+        let key_prefix = format!("{}-{}", partial_refrence.project, partial_refrence.query);
+        if !self.is_fetched_on_demand(&key_prefix) {
+            self.send_empty_resonse(request_id);
+            return;
+        }
+
+        let matches_on_title = !partial_refrence
+            .query
+            .chars()
+            .all(|character| character.is_ascii_digit());
+        let found_tickets = self
+            .jira_resolver
+            .search_project_tickets(partial_refrence.project, partial_refrence.query);
+        // Stored tickets are offered too, so that completion still works when Jira can not
+        // be reached.
+        let ticket_store = self.ticket_store.read().unwrap();
+        let project_prefix = format!("{}-", partial_refrence.project);
+        let stored_tickets = ticket_store.tickets().filter(|ticket| {
+            ticket.key.starts_with(&project_prefix)
+                && !found_tickets
+                    .iter()
+                    .any(|found_ticket| found_ticket.key == ticket.key)
+        });
+        let mut matching_tickets: Vec<(i64, &JiraTicket)> = found_tickets
+            .iter()
+            .chain(stored_tickets)
+            .filter_map(|ticket| {
+                let score = if matches_on_title {
+                    fuzzy_score(partial_refrence.query, &ticket.title)
+                } else {
+                    ticket.key.starts_with(&key_prefix).then_some(0)
+                };
+                score.map(|score| (score, ticket))
+            })
+            .collect();
+        // Stable, so tickets with equal scores stay most recently updated first.
+        matching_tickets
+            .sort_by_key(|(score, ticket)| (!self.jira_resolver.is_assigned_to_me(ticket), -score));
+
+        let replace_range = Range::new(
+            Position::new(
+                text_document_position.position.line,
+                partial_refrence.start_character,
+            ),
+            text_document_position.position,
+        );
+        let completion_items = matching_tickets
+            .into_iter()
+            .enumerate()
+            .map(|(rank, (_, ticket))| CompletionItem {
+                label: ticket.key.to_owned(),
+                label_details: Some(CompletionItemLabelDetails {
+                    detail: Some(format!(" {}", ticket.title)),
+                    description: Some(ticket.status.to_owned()),
+                }),
+                kind: Some(CompletionItemKind::REFERENCE),
+                detail: Some(ticket.title.to_owned()),
+                documentation: Some(Documentation::MarkupContent(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: transpile_atlassian_markup_to_markdown(&ticket.description),
+                })),
+                sort_text: Some(format!("{rank:04}")),
+                // The typed text matched the title rather than the key, so stop the client
+                // from filtering the ticket back out by comparing it against the key.
+                filter_text: matches_on_title.then(|| key_prefix.to_owned()),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                    replace_range,
+                    ticket.key.to_owned(),
+                ))),
+                ..Default::default()
+            })
+            .collect();
+        let response = CompletionResponse::List(CompletionList {
+            is_incomplete: true,
+            items: completion_items,
+        });
+        self.send_response(request_id, &response);
+    }
+
+    fn process_code_action_request(
+        &self,
+        request_id: &RequestId,
+        code_action_params: &CodeActionParams,
+    ) {
+        let uri = &code_action_params.text_document.uri;
+        // Everything needed from the document is gathered up front, so that its lock is
+        // not held while Jira lists the transitions.
+        let mut tickets: Vec<String> = Vec::new();
+        // The client has no way to ask for the comment, so it can only be the selection.
+        let selected_text = {
+            let document_store = self.document_store.read().unwrap();
+            for refrence in self.refrence_finder.read().unwrap().get_refrences(uri) {
+                let InFileRefrenceType::JiraRefrence { ticket } = &refrence.marker else {
+                    continue;
+                };
+                if refrence.range.intersects(&code_action_params.range) && !tickets.contains(ticket)
+                {
+                    tickets.push(ticket.to_owned());
+                }
+            }
+            document_store
+                .get(uri)
+                .map(|document| document.text_in_range(code_action_params.range).trim())
+                .filter(|text| !text.is_empty())
+                .map(str::to_owned)
+        };
+
+        let mut code_actions: Vec<CodeActionOrCommand> = Vec::new();
+        for ticket in tickets {
+            if self.is_cancelled(request_id) {
+                break;
+            }
+            match self.jira_resolver.get_transitions(&ticket) {
+                Ok(transitions) => code_actions.extend(transitions.into_iter().map(|transition| {
+                    let title = if transition.name == transition.to_status {
+                        format!("Move {ticket} to {}", transition.to_status)
+                    } else {
+                        format!(
+                            "{}: move {ticket} to {}",
+                            transition.name, transition.to_status
+                        )
+                    };
+                    ticket_code_action(
+                        title,
+                        TicketCommand::Transition {
+                            key: ticket.to_owned(),
+                            transition_id: transition.id,
+                        },
+                    )
+                })),
+                Err(e) => warn!("Could not list transitions of {ticket} because {e:?}"),
+            }
+            code_actions.push(ticket_code_action(
+                format!("Assign {ticket} to me"),
+                TicketCommand::AssignToMe {
+                    key: ticket.to_owned(),
+                },
+            ));
+            if let Some(selected_text) = &selected_text {
+                code_actions.push(ticket_code_action(
+                    format!("Comment selection on {ticket}"),
+                    TicketCommand::AddComment {
+                        key: ticket.to_owned(),
+                        body: selected_text.to_owned(),
+                    },
+                ));
+            }
+        }
+        self.send_response(request_id, &code_actions);
+    }
+
+    fn process_execute_command_request(
+        &self,
+        request_id: &RequestId,
+        execute_command_params: ExecuteCommandParams,
+    ) {
+        let command_name = execute_command_params.command.to_owned();
+        let Some(ticket_command) =
+            TicketCommand::from_execute_command_params(execute_command_params)
+        else {
+            self.send_error_response(
+                request_id,
+                ErrorCode::InvalidParams,
+                format!("Unknown command or arguments for {command_name}"),
+            );
+            return;
+        };
+        let result = match &ticket_command {
+            TicketCommand::Transition { key, transition_id } => {
+                self.jira_resolver.transition_ticket(key, transition_id)
+            }
+            TicketCommand::AssignToMe { key } => self.jira_resolver.assign_ticket_to_me(key),
+            TicketCommand::AddComment { key, body } => {
+                self.jira_resolver.add_comment(key, body.to_owned())
+            }
+            TicketCommand::ShowRefrences { key } => {
+                self.send_response(request_id, &self.refrence_locations(key));
+                return;
+            }
+        };
+        match result {
+            Ok(()) => {
+                self.send_empty_resonse(request_id);
+                // Fetched again when the views ask for it.
+                self.ticket_store
+                    .write()
+                    .unwrap()
+                    .forget(ticket_command.key());
+                self.refresh_ticket_views();
+            }
+            Err(e) => {
+                let message = format!("Could not update {} in Jira: {e}", ticket_command.key());
+                // Commands are run from menus, where a failed response often goes unseen.
+                self.show_message(MessageType::ERROR, message.to_owned());
+                self.send_error_response(request_id, ErrorCode::RequestFailed, message);
+            }
+        }
+    }
+
+    /// Asks the client to redraw everything that shows ticket details, after tickets changed.
+    fn refresh_ticket_views(&self) {
+        let workspace_capabilities = self.params.capabilities.workspace.as_ref();
+        let inlay_hint_refresh_support = workspace_capabilities
+            .and_then(|workspace| workspace.inlay_hint.as_ref())
+            .and_then(|inlay_hint| inlay_hint.refresh_support)
+            .unwrap_or(false);
+        let code_lens_refresh_support = workspace_capabilities
+            .and_then(|workspace| workspace.code_lens.as_ref())
+            .and_then(|code_lens| code_lens.refresh_support)
+            .unwrap_or(false);
+        let semantic_tokens_refresh_support = workspace_capabilities
+            .and_then(|workspace| workspace.semantic_tokens.as_ref())
+            .and_then(|semantic_tokens| semantic_tokens.refresh_support)
+            .unwrap_or(false);
+        if inlay_hint_refresh_support && self.refrence_display.shows_inlay_hints() {
+            self.send_request::<InlayHintRefreshRequest>(());
+        }
+        if code_lens_refresh_support && self.refrence_display.shows_code_lens() {
+            self.send_request::<CodeLensRefresh>(());
+        }
+        if semantic_tokens_refresh_support {
+            self.send_request::<SemanticTokensRefresh>(());
+        }
+        let open_documents: Vec<Uri> = self
+            .document_store
+            .read()
+            .unwrap()
+            .uris()
+            .cloned()
+            .collect();
+        for uri in open_documents {
+            self.publish_diagnostics(&uri);
+        }
+    }
+
+    fn process_workspace_symbol_request(
+        &self,
+        request_id: &RequestId,
+        workspace_symbol_params: &WorkspaceSymbolParams,
+    ) {
+        let query = workspace_symbol_params.query.trim();
+        let matching_symbols = {
+            let ticket_store = self.ticket_store.read().unwrap();
+            self.matching_workspace_symbols(&ticket_store, query)
+        };
+        // Jump to where the ticket is mentioned, falling back to the ticket itself.
+        let refrence_finder = self.refrence_finder.read().unwrap();
+        let workspace_symbols: Vec<WorkspaceSymbol> = matching_symbols
+            .into_iter()
+            .map(|(key, mut workspace_symbol)| {
+                if let Some((uri, refrence)) = refrence_finder.find_ticket_refrences(&key).next() {
+                    workspace_symbol.location = OneOf::Left(Location::new(
+                        uri.to_owned(),
+                        refrence.range.to_owned().into(),
+                    ));
+                }
+                workspace_symbol
+            })
+            .collect();
+        drop(refrence_finder);
+        self.send_response(
+            request_id,
+            &WorkspaceSymbolResponse::Nested(workspace_symbols),
+        );
+    }
+
+    /// The keys of the tickets best matching the query, with their symbols located at
+    /// their `jira://` documents.
+    fn matching_workspace_symbols(
+        &self,
+        ticket_store: &TicketStore,
+        query: &str,
+    ) -> Vec<(String, WorkspaceSymbol)> {
+        let mut matching_tickets: Vec<(i64, &JiraTicket)> = ticket_store
+            .tickets()
+            .filter_map(|ticket| {
+                if query.is_empty() {
+                    return Some((0, ticket));
+                }
+                [
+                    fuzzy_score(query, &ticket.key),
+                    fuzzy_score(query, &ticket.title),
+                ]
+                .into_iter()
+                .flatten()
+                .max()
+                .map(|score| (score, ticket))
+            })
+            .collect();
+        matching_tickets.sort_by(|(score, ticket), (other_score, other_ticket)| {
+            other_score
+                .cmp(score)
+                .then_with(|| ticket.key.cmp(&other_ticket.key))
+        });
+        matching_tickets.truncate(WORKSPACE_SYMBOL_LIMIT);
+        matching_tickets
+            .into_iter()
+            .map(|(_, ticket)| {
+                let workspace_symbol = WorkspaceSymbol {
+                    name: format!("{} {}", ticket.key, ticket.title),
+                    kind: SymbolKind::KEY,
+                    tags: None,
+                    container_name: Some(ticket.status.to_owned()),
+                    location: OneOf::Left(Location::new(
+                        ticket_document_uri(self.jira_resolver.host(), &ticket.key),
+                        Range::default(),
+                    )),
+                    data: None,
+                };
+                (ticket.key.to_owned(), workspace_symbol)
+            })
+            .collect()
+    }
+
+    fn process_code_lens_request(&self, request_id: &RequestId, code_lens_params: &CodeLensParams) {
+        if !self.refrence_display.shows_code_lens() {
+            self.send_empty_resonse(request_id);
+            return;
+        }
+        let uri = &code_lens_params.text_document.uri;
+        let refrences = self.refrences_in(uri);
+        // Looked up once per ticket, rather than once per refrence to it.
+        let mut locations_by_ticket: HashMap<&str, Vec<Location>> = HashMap::new();
+        {
+            let refrence_finder = self.refrence_finder.read().unwrap();
+            for ticket in refrences.iter().filter_map(jira_ticket_key) {
+                locations_by_ticket
+                    .entry(ticket)
+                    .or_insert_with(|| ticket_refrence_locations(&refrence_finder, ticket));
+            }
+        }
+        self.load_tickets(locations_by_ticket.keys().copied());
+        let shown_by_client = client_shows_references(self.params.client_info.as_ref());
+        let ticket_store = self.ticket_store.read().unwrap();
+        let code_lenses: Vec<CodeLens> = refrences
+            .iter()
+            .filter_map(|refrence| {
+                let ticket = jira_ticket_key(refrence)?;
+                let jira_ticket = ticket_store.ticket(ticket)?;
+                let locations = &locations_by_ticket[ticket];
+                let refrence_count = locations.len();
+                let title = format!(
+                    "{} · {} · {} · {} {}",
+                    ticket,
+                    jira_ticket.status,
+                    jira_ticket.assignee.as_deref().unwrap_or("Unassigned"),
+                    refrence_count,
+                    if refrence_count == 1 {
+                        "reference"
+                    } else {
+                        "references"
+                    },
+                );
+                Some(CodeLens {
+                    range: refrence.range.to_owned().into(),
+                    command: Some(if shown_by_client {
+                        show_refrences_command(
+                            title,
+                            uri,
+                            refrence.range.start_position(),
+                            locations,
+                        )
+                    } else {
+                        TicketCommand::ShowRefrences {
+                            key: ticket.to_owned(),
+                        }
+                        .into_command(title)
+                    }),
+                    data: None,
+                })
+            })
+            .collect();
+        self.send_response(request_id, &code_lenses);
+    }
+
+    fn process_references_request(
+        &self,
+        request_id: &RequestId,
+        reference_params: &ReferenceParams,
+    ) {
+        let position = reference_params.text_document_position.position;
+        let ticket = self
+            .refrences_in(&reference_params.text_document_position.text_document.uri)
+            .into_iter()
+            .find(|refrence| refrence.range.contains_position(position))
+            .and_then(|refrence| match refrence.marker {
+                InFileRefrenceType::JiraRefrence { ticket } => Some(ticket),
+                _ => None,
+            });
+        let Some(ticket) = ticket else {
+            self.send_empty_resonse(request_id);
+            return;
+        };
+        self.send_response(request_id, &self.refrence_locations(&ticket));
+    }
+
+    fn process_semantic_tokens_full_request(
+        &self,
+        request_id: &RequestId,
+        semantic_tokens_params: &SemanticTokensParams,
+    ) {
+        let semantic_tokens =
+            self.ticket_semantic_tokens(&semantic_tokens_params.text_document.uri, None);
+        self.send_response(request_id, &SemanticTokensResult::Tokens(semantic_tokens));
+    }
+
+    fn process_semantic_tokens_range_request(
+        &self,
+        request_id: &RequestId,
+        semantic_tokens_range_params: &SemanticTokensRangeParams,
+    ) {
+        let semantic_tokens = self.ticket_semantic_tokens(
+            &semantic_tokens_range_params.text_document.uri,
+            Some(&semantic_tokens_range_params.range),
+        );
+        self.send_response(
+            request_id,
+            &SemanticTokensRangeResult::Tokens(semantic_tokens),
+        );
+    }
+
+    fn ticket_semantic_tokens(&self, uri: &Uri, range: Option<&Range>) -> SemanticTokens {
+        let (refrences, ticket_store) = self.load_document_tickets(uri);
+        let ticket_tokens = refrences
+            .iter()
+            .filter(|refrence| range.is_none_or(|range| refrence.range.intersects(range)))
+            .filter_map(|refrence| match &refrence.marker {
+                InFileRefrenceType::JiraRefrence { ticket }
+                    if self.is_fetched_on_demand(ticket) =>
+                {
+                    let jira_ticket = ticket_store.ticket(ticket);
+                    let is_assigned_to_me =
+                        jira_ticket.is_some_and(|x| self.jira_resolver.is_assigned_to_me(x));
+                    Some((
+                        refrence.range.to_owned(),
+                        ticket_token_modifiers(jira_ticket, is_assigned_to_me),
+                    ))
+                }
+                _ => None,
+            });
+        SemanticTokens {
+            result_id: None,
+            data: encode_ticket_tokens(ticket_tokens),
+        }
+    }
+
     fn process_goto_definition(
         &self,
         request_id: &RequestId,
@@ -227,48 +1394,231 @@ impl Server {
         let position = goto_definition_params
             .text_document_position_params
             .position;
-        let position = Position {
-            line: position.line - 1,
-            character: 0,
+        let ticket = self
+            .refrences_in(
+                &goto_definition_params
+                    .text_document_position_params
+                    .text_document
+                    .uri,
+            )
+            .into_iter()
+            .find(|refrence| refrence.range.contains_position(position))
+            .and_then(|refrence| match refrence.marker {
+                InFileRefrenceType::JiraRefrence { ticket } => Some(ticket),
+                _ => None,
+            });
+
+        match ticket {
+            Some(ticket) => {
+                let response = GotoDefinitionResponse::Scalar(Location::new(
+                    ticket_document_uri(self.jira_resolver.host(), &ticket),
+                    Range::default(),
+                ));
+                self.send_response(request_id, &response);
+            }
+            None => self.send_empty_resonse(request_id),
+        }
+    }
+
+    fn process_prepare_type_hierarchy_request(
+        &self,
+        request_id: &RequestId,
+        prepare_params: &TypeHierarchyPrepareParams,
+    ) {
+        info!("got prepareTypeHierarchy request #{request_id}: {prepare_params:?}");
+        let position = prepare_params.text_document_position_params.position;
+        let key = self
+            .refrences_in(
+                &prepare_params
+                    .text_document_position_params
+                    .text_document
+                    .uri,
+            )
+            .into_iter()
+            .find(|refrence| refrence.range.contains_position(position))
+            .and_then(|refrence| match refrence.marker {
+                InFileRefrenceType::JiraRefrence { ticket } => Some(ticket),
+                _ => None,
+            });
+        let Some(key) = key else {
+            self.send_empty_resonse(request_id);
+            return;
         };
-        let new_location = Location::new(
-            goto_definition_params
-                .text_document_position_params
-                .text_document
-                .uri
-                .to_owned(),
-            Range {
-                start: position,
-                end: position,
-            },
-        );
-        let other_file = Location::new(
-            Uri::from_str("/tmp/aaa.txt").unwrap(),
-            Range {
-                start: Position {
-                    line: 0,
-                    character: 0,
-                },
-                end: position,
-            },
+        self.load_tickets([key.as_str()]);
+        let items: Option<Vec<TypeHierarchyItem>> = self
+            .ticket_store
+            .read()
+            .unwrap()
+            .ticket(&key)
+            .map(|ticket| vec![type_hierarchy_item(self.jira_resolver.host(), ticket)]);
+        self.send_response(request_id, &items);
+    }
+
+    /// Walks up to the parent of the ticket, such as its epic.
+    fn process_type_hierarchy_supertypes_request(
+        &self,
+        request_id: &RequestId,
+        supertypes_params: &TypeHierarchySupertypesParams,
+    ) {
+        let key = ticket_key_from_item(&supertypes_params.item);
+        self.load_tickets([key]);
+        let items: Vec<TypeHierarchyItem> = self
+            .ticket_store
+            .read()
+            .unwrap()
+            .ticket(key)
+            .and_then(|ticket| ticket.parent.as_ref())
+            .map(|parent| linked_type_hierarchy_item(self.jira_resolver.host(), parent))
+            .into_iter()
+            .collect();
+        self.send_response(request_id, &items);
+    }
+
+    /// Lists the tickets of an epic, or the subtasks of any other ticket. Jira is always
+    /// asked, as tickets do not know the tickets of their epic.
+    fn process_type_hierarchy_subtypes_request(
+        &self,
+        request_id: &RequestId,
+        subtypes_params: &TypeHierarchySubtypesParams,
+    ) {
+        let key = ticket_key_from_item(&subtypes_params.item);
+        let children = match self.jira_resolver.fetch_children(key) {
+            Ok(children) => children,
+            Err(e) => {
+                self.send_error_response(
+                    request_id,
+                    ErrorCode::RequestFailed,
+                    format!("Could not fetch the children of {key} from Jira: {e}"),
+                );
+                return;
+            }
+        };
+        let items: Vec<TypeHierarchyItem> = children
+            .iter()
+            .map(|child| type_hierarchy_item(self.jira_resolver.host(), child))
+            .collect();
+        self.send_response(request_id, &items);
+        let mut ticket_store = self.ticket_store.write().unwrap();
+        ticket_store.extend(
+            children
+                .into_iter()
+                .map(|child| (child.key.to_owned(), TicketLookup::Found(Box::new(child)))),
         );
-        let response = GotoDefinitionResponse::Array(vec![new_location, other_file]);
-        // let result = Some(new_location);
-        self.send_response(request_id, &response);
+        ticket_store.save();
+    }
+
+    fn process_text_document_content_request(
+        &self,
+        request_id: &RequestId,
+        text_document_content_params: &TextDocumentContentParams,
+    ) {
+        let Some(key) = ticket_key_from_document_uri(&text_document_content_params.uri) else {
+            self.send_error_response(
+                request_id,
+                ErrorCode::InvalidParams,
+                format!(
+                    "Can only provide {JIRA_SCHEME} documents, not {}",
+                    text_document_content_params.uri.as_str()
+                ),
+            );
+            return;
+        };
+        self.load_tickets([key]);
+        match self.ticket_store.read().unwrap().ticket(key) {
+            Some(ticket) => {
+                let response = TextDocumentContentResult {
+                    text: render_ticket_document(ticket),
+                };
+                self.send_response(request_id, &response);
+            }
+            None => self.send_error_response(
+                request_id,
+                ErrorCode::InvalidParams,
+                format!("Could not find ticket {key} in Jira"),
+            ),
+        }
     }
 
     fn send_empty_resonse(&self, request_id: &RequestId) {
+        // Without a result the response would not be a valid success.
         let response = Response {
             id: request_id.to_owned(),
-            result: None,
+            result: Some(serde_json::Value::Null),
             error: None,
         };
+        self.send_response_message(response);
+    }
+
+    fn send_cancelled_response(&self, request_id: &RequestId) {
+        self.send_error_response(
+            request_id,
+            ErrorCode::RequestCanceled,
+            String::from("Request was cancelled"),
+        );
+    }
+
+    /// Answers a request, unless the client has cancelled it in the meantime.
+    fn send_response_message(&self, response: Response) {
+        let cancelled = self
+            .pending_requests
+            .lock()
+            .unwrap()
+            .remove(&response.id)
+            .unwrap_or(false);
+        let response = if cancelled {
+            Response::new_err(
+                response.id,
+                ErrorCode::RequestCanceled as i32,
+                String::from("Request was cancelled"),
+            )
+        } else {
+            response
+        };
         self.connection
             .sender
             .send(Message::Response(response))
             .unwrap();
     }
 
+    fn next_progress_token(&self) -> i32 {
+        self.next_progress_token.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn send_notification<N: lsp_types::notification::Notification>(&self, params: N::Params) {
+        let notification = lsp_server::Notification::new(N::METHOD.to_owned(), params);
+        self.connection
+            .sender
+            .send(Message::Notification(notification))
+            .unwrap();
+    }
+
+    /// Pops up a message in the editor, for failures the user has to do something about.
+    fn show_message(&self, typ: MessageType, message: String) {
+        self.send_notification::<ShowMessage>(ShowMessageParams { typ, message });
+    }
+
+    /// Writes to the editor's log for the server, for failures that are recovered from.
+    fn log_message(&self, typ: MessageType, message: String) {
+        self.send_notification::<LogMessage>(LogMessageParams { typ, message });
+    }
+
+    fn send_request<R: lsp_types::request::Request>(&self, params: R::Params) {
+        let request = lsp_server::Request::new(
+            RequestId::from(self.next_request_id.fetch_add(1, Ordering::Relaxed)),
+            R::METHOD.to_owned(),
+            params,
+        );
+        self.connection
+            .sender
+            .send(Message::Request(request))
+            .unwrap();
+    }
+
+    fn send_error_response(&self, request_id: &RequestId, code: ErrorCode, message: String) {
+        let response = Response::new_err(request_id.to_owned(), code as i32, message);
+        self.send_response_message(response);
+    }
+
     fn send_response<T: Serialize>(&self, request_id: &RequestId, response: &T) {
         let result = serde_json::to_value(&response).unwrap();
         let response = Response {
@@ -276,20 +1626,189 @@ impl Server {
             result: Some(result),
             error: None,
         };
-        self.connection
-            .sender
-            .send(Message::Response(response))
-            .unwrap();
+        self.send_response_message(response);
+    }
+}
+
+fn ticket_refrence_locations(refrence_finder: &RefrenceFinder, ticket: &str) -> Vec<Location> {
+    refrence_finder
+        .find_ticket_refrences(ticket)
+        .map(|(uri, refrence)| Location::new(uri.to_owned(), refrence.range.to_owned().into()))
+        .collect()
+}
+
+fn jira_ticket_key(refrence: &InFileRefrence) -> Option<&str> {
+    match &refrence.marker {
+        InFileRefrenceType::JiraRefrence { ticket } => Some(ticket),
+        _ => None,
     }
 }
 
-fn cast<R>(request: lsp_server::Request) -> Result<(RequestId, R::Params), String>
+fn ticket_code_action(title: String, ticket_command: TicketCommand) -> CodeActionOrCommand {
+    CodeActionOrCommand::CodeAction(CodeAction {
+        command: Some(ticket_command.into_command(title.to_owned())),
+        title,
+        ..Default::default()
+    })
+}
+
+fn cast_notification<N>(notification: lsp_server::Notification) -> Result<N::Params, String>
+where
+    N: lsp_types::notification::Notification,
+    N::Params: serde::de::DeserializeOwned,
+{
+    notification.extract(N::METHOD).map_err(|e| e.to_string())
+}
+
+fn cast<R>(request: lsp_server::Request) -> Result<(RequestId, R::Params), ResponseError>
 where
     R: lsp_types::request::Request,
     R::Params: serde::de::DeserializeOwned,
 {
-    match request.extract(R::METHOD) {
-        Ok(it) => Ok(it),
-        Err(_) => Err(String::from("There was an error")),
+    request
+        .extract(R::METHOD)
+        .map_err(|e| response_error(ErrorCode::InvalidParams, e.to_string()))
+}
+
+fn response_error(code: ErrorCode, message: String) -> ResponseError {
+    ResponseError {
+        code: code as i32,
+        message,
+        data: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A server on the other end of an in-memory connection, reaching for a Jira that is
+    /// not there.
+    fn server_with_client() -> (Server, Connection) {
+        let (server_connection, client_connection) = Connection::memory();
+        let config: Config = toml::from_str(
+            r#"
+            [jira]
+            host = "https://127.0.0.1:9"
+            email = "me@example.com"
+            api_token = "token"
+
+            [cache]
+            sync_interval_minutes = 0
+            "#,
+        )
+        .unwrap();
+        let Ok(server) = Server::new(server_connection, InitializeParams::default(), &config)
+        else {
+            panic!("could not create the server");
+        };
+        (server, client_connection)
+    }
+
+    fn response_to(client: &Connection, id: i32) -> Response {
+        client
+            .receiver
+            .iter()
+            .find_map(|msg| match msg {
+                Message::Response(response) if response.id == RequestId::from(id) => Some(response),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    /// Sends the requests to a running server, and collects the responses to them before
+    /// shutting the server down.
+    fn responses_to(requests: &[(&str, serde_json::Value)]) -> Vec<Response> {
+        let (server, client) = server_with_client();
+        let server_thread = thread::spawn(move || server.run_loop().unwrap());
+        let responses = requests
+            .iter()
+            .zip(1..)
+            .map(|((method, params), id)| {
+                client
+                    .sender
+                    .send(Message::Request(lsp_server::Request::new(
+                        RequestId::from(id),
+                        method.to_string(),
+                        params.to_owned(),
+                    )))
+                    .unwrap();
+                response_to(&client, id)
+            })
+            .collect();
+        client
+            .sender
+            .send(Message::Request(lsp_server::Request::new(
+                RequestId::from(0),
+                String::from("shutdown"),
+                serde_json::Value::Null,
+            )))
+            .unwrap();
+        response_to(&client, 0);
+        client
+            .sender
+            .send(Message::Notification(lsp_server::Notification::new(
+                String::from("exit"),
+                serde_json::Value::Null,
+            )))
+            .unwrap();
+        server_thread.join().unwrap();
+        responses
+    }
+
+    #[test]
+    fn unknown_requests_are_not_found() {
+        let responses = responses_to(&[("textDocument/unknown", json!({}))]);
+
+        let error = responses[0].error.as_ref().unwrap();
+        assert_eq!(error.code, ErrorCode::MethodNotFound as i32);
+        assert_eq!(error.message, "Unknown request textDocument/unknown");
+    }
+
+    #[test]
+    fn malformed_params_are_invalid() {
+        let responses = responses_to(&[(HoverRequest::METHOD, json!({ "position": 12 }))]);
+
+        let error = responses[0].error.as_ref().unwrap();
+        assert_eq!(error.code, ErrorCode::InvalidParams as i32);
+        assert!(error.message.contains("invalid type"), "{}", error.message);
+    }
+
+    #[test]
+    fn requests_without_an_answer_succeed_with_null() {
+        let responses = responses_to(&[(
+            HoverRequest::METHOD,
+            json!({
+                "textDocument": { "uri": "file:///tmp/notes.md" },
+                "position": { "line": 0, "character": 0 }
+            }),
+        )]);
+
+        assert!(responses[0].error.is_none());
+        assert_eq!(responses[0].result, Some(serde_json::Value::Null));
+        assert_eq!(
+            serde_json::to_value(&responses[0]).unwrap(),
+            json!({ "id": 1, "result": null })
+        );
+    }
+
+    #[test]
+    fn requests_are_answered_when_handling_them_panics() {
+        let (server, client) = server_with_client();
+        let server = Arc::new(server);
+
+        Server::handle_request_in_background(
+            &server,
+            lsp_server::Request::new(
+                RequestId::from(1),
+                HoverRequest::METHOD.to_owned(),
+                json!({}),
+            ),
+            |_, _| panic!("handler failed"),
+        );
+
+        let error = response_to(&client, 1).error.unwrap();
+        assert_eq!(error.code, ErrorCode::InternalError as i32);
     }
 }
@@ -1,15 +1,22 @@
 #![allow(clippy::print_stderr)]
 
-use log::info;
+use log::{error, info};
 use std::error::Error;
 use stderrlog;
 
-use lsp_types::{DocumentLinkOptions, OneOf, WorkDoneProgressOptions};
-use lsp_types::{InitializeParams, ServerCapabilities};
+use lsp_types::notification::{Notification, ShowMessage};
+use lsp_types::{
+    CodeActionProviderCapability, CodeLensOptions, CompletionOptions, DocumentLinkOptions,
+    ExecuteCommandOptions, OneOf, SemanticTokensFullOptions, SemanticTokensOptions,
+    SemanticTokensServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind,
+    TextDocumentSyncOptions, WorkDoneProgressOptions,
+};
+use lsp_types::{InitializeParams, MessageType, ServerCapabilities, ShowMessageParams};
 
-use lsp_server::Connection;
-use refrences_lsp::config::Config;
+use lsp_server::{Connection, ErrorCode, Message, Response};
+use refrences_lsp::config::{Config, RefrenceDisplay};
 use refrences_lsp::Server;
+use refrences_lsp::{semantic_tokens_legend, JIRA_SCHEME, TICKET_COMMANDS};
 
 fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
     stderrlog::new()
@@ -21,8 +28,14 @@ fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
     // Note that  we must have our logging only write out to stderr.
     info!("starting generic LSP server");
 
-    let config = Config::from_file().unwrap();
-    info!("Using email {}", config.jira.email);
+    let config = Config::from_file();
+    match &config {
+        Ok(config) => info!("Using email {}", config.jira.email),
+        Err(e) => error!("Could not load config: {e}"),
+    }
+    let refrence_display = config
+        .as_ref()
+        .map_or(RefrenceDisplay::default(), |config| config.refrence_display);
 
     // Create the transport. Includes the stdio (stdin and stdout) versions but this could
     // also be implemented to use sockets or HTTP.
@@ -31,9 +44,42 @@ fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
     let (connection, io_threads) = Connection::stdio();
 
     // Run the server and wait for the two threads to end (typically by trigger LSP Exit event).
-    let server_capabilities = serde_json::to_value(&ServerCapabilities {
+    let mut server_capabilities = serde_json::to_value(&ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::INCREMENTAL),
+                ..Default::default()
+            },
+        )),
         definition_provider: Some(OneOf::Left(true)),
-        inlay_hint_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: semantic_tokens_legend(),
+                range: Some(true),
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                ..Default::default()
+            },
+        )),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        execute_command_provider: Some(ExecuteCommandOptions {
+            commands: TICKET_COMMANDS.map(String::from).to_vec(),
+            ..Default::default()
+        }),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![String::from("-")]),
+            ..Default::default()
+        }),
+        inlay_hint_provider: refrence_display
+            .shows_inlay_hints()
+            .then_some(OneOf::Left(true)),
+        code_lens_provider: refrence_display
+            .shows_code_lens()
+            .then_some(CodeLensOptions {
+                resolve_provider: Some(false),
+            }),
         hover_provider: Some(lsp_types::HoverProviderCapability::Simple(true)),
         document_link_provider: Some(DocumentLinkOptions {
             resolve_provider: Some(true),
@@ -44,6 +90,13 @@ fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
         ..Default::default()
     })
     .unwrap();
+    // Lets clients fetch the `jira://` documents go-to-definition points at, ahead of
+    // `workspace.textDocumentContent` landing in `lsp_types`.
+    server_capabilities["workspace"]["textDocumentContent"] = serde_json::json!({
+        "schemes": [JIRA_SCHEME],
+    });
+    // `lsp_types` has the type hierarchy requests, but not the capability announcing them.
+    server_capabilities["typeHierarchyProvider"] = serde_json::Value::Bool(true);
     let initialization_params: InitializeParams = match connection.initialize(server_capabilities) {
         Ok(it) => serde_json::from_value(it).unwrap(),
         Err(e) => {
@@ -53,11 +106,54 @@ fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
             return Err(e.into());
         }
     };
-    let mut server = Server::new(connection, initialization_params, &config);
-    let _ = server.run_loop();
+    match config {
+        Ok(config) => match Server::new(connection, initialization_params, &config) {
+            Ok(server) => {
+                let _ = server.run_loop();
+            }
+            Err((connection, e)) => run_without_config(
+                &connection,
+                format!("refrences-lsp could not connect to Jira: {e}"),
+            )?,
+        },
+        Err(e) => run_without_config(
+            &connection,
+            format!("refrences-lsp could not load its config: {e}"),
+        )?,
+    }
     io_threads.join()?;
 
     // Shut down gracefully.
     info!("shutting down server");
     Ok(())
 }
+
+/// Keeps the editor session alive without a usable config, telling the user what is wrong
+/// and failing every request until the editor shuts the server down.
+fn run_without_config(
+    connection: &Connection,
+    message: String,
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    connection
+        .sender
+        .send(Message::Notification(lsp_server::Notification::new(
+            ShowMessage::METHOD.to_owned(),
+            ShowMessageParams {
+                typ: MessageType::ERROR,
+                message: message.to_owned(),
+            },
+        )))?;
+    for msg in &connection.receiver {
+        if let Message::Request(request) = msg {
+            if connection.handle_shutdown(&request)? {
+                break;
+            }
+            connection.sender.send(Message::Response(Response::new_err(
+                request.id,
+                ErrorCode::RequestFailed as i32,
+                message.to_owned(),
+            )))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use lsp_types::notification::Progress;
+use lsp_types::request::WorkDoneProgressCreate;
+use lsp_types::{
+    NumberOrString, ProgressParams, ProgressParamsValue, ProgressToken, WorkDoneProgress,
+    WorkDoneProgressBegin, WorkDoneProgressCreateParams, WorkDoneProgressEnd,
+    WorkDoneProgressReport,
+};
+
+use crate::Server;
+
+/// Shows the user how far along something slow is through `window/workDoneProgress`.
+/// Does nothing for clients that do not support it.
+pub struct ProgressReporter<'a> {
+    server: &'a Server,
+    token: Option<ProgressToken>,
+    last_percentage: u32,
+}
+
+impl<'a> ProgressReporter<'a> {
+    pub fn begin(server: &'a Server, title: &str) -> ProgressReporter<'a> {
+        let work_done_progress_support = server
+            .params
+            .capabilities
+            .window
+            .as_ref()
+            .and_then(|window| window.work_done_progress)
+            .unwrap_or(false);
+        let token = work_done_progress_support.then(|| {
+            let token =
+                NumberOrString::String(format!("refrences-lsp/{}", server.next_progress_token()));
+            server.send_request::<WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                token: token.to_owned(),
+            });
+            token
+        });
+        let progress_reporter = ProgressReporter {
+            server,
+            token,
+            last_percentage: 0,
+        };
+        progress_reporter.send(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+            title: title.to_owned(),
+            cancellable: Some(false),
+            message: None,
+            percentage: Some(0),
+        }));
+        progress_reporter
+    }
+
+    /// Only sends a report when the percentage has moved, so that progress through
+    /// thousands of items does not flood the client.
+    pub fn report(&mut self, done: usize, total: usize, message: impl FnOnce() -> String) {
+        let percentage = percentage(done, total);
+        if percentage == self.last_percentage {
+            return;
+        }
+        self.last_percentage = percentage;
+        self.send(WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: Some(false),
+            message: Some(message()),
+            percentage: Some(percentage),
+        }));
+    }
+
+    pub fn end(self, message: String) {
+        self.send(WorkDoneProgress::End(WorkDoneProgressEnd {
+            message: Some(message),
+        }));
+    }
+
+    fn send(&self, work_done_progress: WorkDoneProgress) {
+        if let Some(token) = &self.token {
+            self.server.send_notification::<Progress>(ProgressParams {
+                token: token.to_owned(),
+                value: ProgressParamsValue::WorkDone(work_done_progress),
+            });
+        }
+    }
+}
+
+fn percentage(done: usize, total: usize) -> u32 {
+    if total == 0 {
+        return 100;
+    }
+    (done.min(total) * 100 / total) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentage_is_rounded_down_and_capped() {
+        assert_eq!(percentage(0, 3), 0);
+        assert_eq!(percentage(2, 3), 66);
+        assert_eq!(percentage(5, 3), 100);
+        assert_eq!(percentage(0, 0), 100);
+    }
+}
@@ -1,8 +1,9 @@
 use log::info;
 use regex::Regex;
-use std::{collections::HashMap, fs, time::SystemTime};
+use std::collections::{BTreeMap, BTreeSet};
 
-use lsp_types::{Position, Range};
+use lsp_types::{Position, Range, Uri};
+use serde::{Deserialize, Serialize};
 
 /// A character range within a single line.
 /// The same as a `lsP_types::Range` with `Range.start.line == Range.end.line`
@@ -13,98 +14,131 @@ pub struct InlineRange {
     end_character: u32,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum InFileRefrenceType {
     JiraRefrence { ticket: String },
     GitHubUrlRefrence { url: String },
     GitLabUrlRefrence { url: String },
 }
 
+#[derive(Clone)]
 pub struct InFileRefrence {
     pub marker: InFileRefrenceType,
     pub range: InlineRange,
 }
 
-struct CachedFileRefrence {
-    last_modified_time: SystemTime,
-    refrences: Vec<InFileRefrence>,
+/// A ticket key that is still being typed, such as `AUTO-` or `AUTO-login`.
+pub struct PartialJiraRefrence<'a> {
+    pub project: &'a str,
+    /// Whatever has been typed after the `-`.
+    pub query: &'a str,
+    pub start_character: u32,
 }
 
 pub struct RefrenceFinder {
-    file_refrences_map: HashMap<String, CachedFileRefrence>,
+    /// Ordered so that searches across documents give stable results.
+    document_refrences_map: BTreeMap<Uri, Vec<InFileRefrence>>,
     refrence_regex: Regex,
+    partial_refrence_regex: Regex,
 }
 
 impl RefrenceFinder {
     pub fn new() -> RefrenceFinder {
         RefrenceFinder {
-            file_refrences_map: HashMap::new(),
-            refrence_regex: Regex::new(r"(?<jira_ticket>[A-Z]{3,}-\d+)|(?<new_line>\n)").unwrap(),
+            document_refrences_map: BTreeMap::new(),
+            refrence_regex: Regex::new(r"(?<jira_ticket>[A-Z]{3,}-\d+)").unwrap(),
+            partial_refrence_regex: Regex::new(r"\b(?<project>[A-Z]{3,})-(?<query>\w*)$").unwrap(),
         }
     }
-    // |ABC-123\n
 
-    pub fn get_refrences<'a>(
-        &'a mut self,
-        file_path: &'a str,
-    ) -> impl Iterator<Item = &InFileRefrence> {
-        if !self.file_refrences_map.contains_key(file_path) {
-            self.find_refrences_in_file(file_path);
-        }
-        let cached_refrences = self.file_refrences_map.get(file_path).unwrap();
-        let last_modified_time = fs::metadata(file_path)
-            .expect("uh oh todo file path errors")
-            .modified()
-            .expect("todo handle error");
-        if cached_refrences.last_modified_time < last_modified_time {
-            self.find_refrences_in_file(file_path);
-        }
-        let cached_refrences = self.file_refrences_map.get(file_path).unwrap();
-        cached_refrences.refrences.iter()
-    }
-
-    fn find_refrences_in_file(&mut self, file_path: &str) {
-        info!("Analysing refrences for {file_path}");
-        let mut current_line = 0;
-        let mut line_start_position = 0;
-        let file_contents = fs::read_to_string(file_path).expect("TODO: wrong file path handling");
-        let last_modified_time = fs::metadata(file_path)
-            .expect("uh oh todo file path errors")
-            .modified()
-            .expect("todo handle error");
-
-        let refrences = self
-            .refrence_regex
-            .captures_iter(&file_contents)
-            .filter_map(|found_match| {
-                if let Some(found_match) = found_match.name("new_line") {
-                    current_line += 1;
-                    line_start_position = found_match.start();
-                    return None;
-                }
-                if let Some(found_match) = found_match.name("jira_ticket") {
-                    return Some(InFileRefrence {
-                        marker: InFileRefrenceType::JiraRefrence {
-                            ticket: found_match.as_str().to_owned(),
-                        },
-                        range: InlineRange {
-                            line: current_line,
-                            start_character: (found_match.start() - line_start_position - 1) as u32,
-                            end_character: (found_match.end() - line_start_position) as u32,
-                        },
-                    });
-                }
-                panic!("Missing regex capture group");
+    /// Finds the ticket key being typed at the end of `line_before_cursor`.
+    pub fn find_partial_refrence<'a>(
+        &self,
+        line_before_cursor: &'a str,
+    ) -> Option<PartialJiraRefrence<'a>> {
+        let found_match = self.partial_refrence_regex.captures(line_before_cursor)?;
+        let project = found_match.name("project")?;
+        Some(PartialJiraRefrence {
+            project: project.as_str(),
+            query: found_match.name("query")?.as_str(),
+            start_character: utf16_length(&line_before_cursor[..project.start()]),
+        })
+    }
+
+    /// The refrences found the last time the document was scanned.
+    /// Documents that have never been scanned have no refrences.
+    pub fn get_refrences<'a>(&'a self, uri: &Uri) -> impl Iterator<Item = &'a InFileRefrence> {
+        self.document_refrences_map
+            .get(uri)
+            .into_iter()
+            .flat_map(|refrences| refrences.iter())
+    }
+
+    /// Rescans the document, replacing any refrences previously found in it.
+    pub fn scan_document(&mut self, uri: &Uri, document_contents: &str) {
+        info!("Analysing refrences for {}", uri.as_str());
+        let refrences = self.find_refrences(document_contents);
+        self.document_refrences_map.insert(uri.clone(), refrences);
+    }
+
+    pub fn forget_document(&mut self, uri: &Uri) {
+        self.document_refrences_map.remove(uri);
+    }
+
+    /// Every refrence to the ticket across all scanned documents.
+    pub fn find_ticket_refrences<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> impl Iterator<Item = (&'a Uri, &'a InFileRefrence)> {
+        self.document_refrences_map
+            .iter()
+            .flat_map(|(uri, refrences)| refrences.iter().map(move |refrence| (uri, refrence)))
+            .filter(move |(_, refrence)| match &refrence.marker {
+                InFileRefrenceType::JiraRefrence { ticket } => ticket == key,
+                _ => false,
             })
-            .collect();
-        self.file_refrences_map.insert(
-            file_path.to_owned(),
-            CachedFileRefrence {
-                refrences,
-                last_modified_time,
-            },
-        );
     }
+
+    /// The key of every ticket refrenced in any scanned document, each once.
+    pub fn ticket_keys(&self) -> BTreeSet<&str> {
+        self.document_refrences_map
+            .values()
+            .flatten()
+            .filter_map(|refrence| match &refrence.marker {
+                InFileRefrenceType::JiraRefrence { ticket } => Some(ticket.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn find_refrences(&self, document_contents: &str) -> Vec<InFileRefrence> {
+        document_contents
+            .split('\n')
+            .enumerate()
+            .flat_map(|(line_number, line)| {
+                self.refrence_regex
+                    .captures_iter(line)
+                    .filter_map(move |found_match| {
+                        let found_match = found_match.name("jira_ticket")?;
+                        Some(InFileRefrence {
+                            marker: InFileRefrenceType::JiraRefrence {
+                                ticket: found_match.as_str().to_owned(),
+                            },
+                            range: InlineRange {
+                                line: line_number as u32,
+                                start_character: utf16_length(&line[..found_match.start()]),
+                                end_character: utf16_length(&line[..found_match.end()]),
+                            },
+                        })
+                    })
+            })
+            .collect()
+    }
+}
+
+/// LSP positions count characters in UTF-16 code units.
+fn utf16_length(text: &str) -> u32 {
+    text.encode_utf16().count() as u32
 }
 
 impl InlineRange {
@@ -114,6 +148,12 @@ impl InlineRange {
             && other_position.character < self.end_character
     }
 
+    pub fn intersects(&self, range: &Range) -> bool {
+        let start = self.start_position();
+        let end = self.end_position();
+        range.start <= end && start <= range.end
+    }
+
     pub fn start_position(&self) -> Position {
         Position {
             line: self.line,
@@ -162,3 +202,112 @@ impl Into<Range> for InlineRange {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn found_tickets(refrence_finder: &RefrenceFinder, uri: &Uri) -> Vec<(String, Range)> {
+        refrence_finder
+            .get_refrences(uri)
+            .map(|refrence| match &refrence.marker {
+                InFileRefrenceType::JiraRefrence { ticket } => {
+                    (ticket.to_owned(), refrence.range.to_owned().into())
+                }
+                _ => panic!("Expected a jira refrence"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_tickets_with_their_ranges() {
+        let uri = Uri::from_str("file:///tmp/main.rs").unwrap();
+        let mut refrence_finder = RefrenceFinder::new();
+        refrence_finder.scan_document(&uri, "AUTO-1 first\n// TODO(AUTO-23): later\n");
+
+        assert_eq!(
+            found_tickets(&refrence_finder, &uri),
+            vec![
+                (
+                    "AUTO-1".to_owned(),
+                    Range::new(Position::new(0, 0), Position::new(0, 6))
+                ),
+                (
+                    "AUTO-23".to_owned(),
+                    Range::new(Position::new(1, 8), Position::new(1, 15))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn ranges_count_utf16_code_units() {
+        let uri = Uri::from_str("untitled:Untitled-1").unwrap();
+        let mut refrence_finder = RefrenceFinder::new();
+        refrence_finder.scan_document(&uri, "🎉 AUTO-1");
+
+        assert_eq!(
+            found_tickets(&refrence_finder, &uri),
+            vec![(
+                "AUTO-1".to_owned(),
+                Range::new(Position::new(0, 3), Position::new(0, 9))
+            )]
+        );
+    }
+
+    #[test]
+    fn finds_partial_refrence_being_typed() {
+        let refrence_finder = RefrenceFinder::new();
+
+        let partial = refrence_finder
+            .find_partial_refrence("// TODO(AUTO-log")
+            .unwrap();
+        assert_eq!(partial.project, "AUTO");
+        assert_eq!(partial.query, "log");
+        assert_eq!(partial.start_character, 8);
+
+        assert!(refrence_finder
+            .find_partial_refrence("AUTO-12 done")
+            .is_none());
+        assert!(refrence_finder.find_partial_refrence("GOTO").is_none());
+    }
+
+    #[test]
+    fn finds_ticket_refrences_across_documents() {
+        let first_uri = Uri::from_str("file:///tmp/a.md").unwrap();
+        let second_uri = Uri::from_str("file:///tmp/b.rs").unwrap();
+        let mut refrence_finder = RefrenceFinder::new();
+        refrence_finder.scan_document(&second_uri, "// AUTO-1\n// AUTO-2");
+        refrence_finder.scan_document(&first_uri, "AUTO-2 and AUTO-2");
+
+        let found: Vec<(&str, u32)> = refrence_finder
+            .find_ticket_refrences("AUTO-2")
+            .map(|(uri, refrence)| (uri.as_str(), refrence.range.start_position().line))
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                ("file:///tmp/a.md", 0),
+                ("file:///tmp/a.md", 0),
+                ("file:///tmp/b.rs", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn rescanning_replaces_refrences() {
+        let uri = Uri::from_str("file:///tmp/notes.md").unwrap();
+        let mut refrence_finder = RefrenceFinder::new();
+        refrence_finder.scan_document(&uri, "AUTO-1");
+        refrence_finder.scan_document(&uri, "nothing here");
+
+        assert!(found_tickets(&refrence_finder, &uri).is_empty());
+
+        refrence_finder.scan_document(&uri, "AUTO-2");
+        refrence_finder.forget_document(&uri);
+
+        assert!(found_tickets(&refrence_finder, &uri).is_empty());
+    }
+}
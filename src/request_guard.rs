@@ -0,0 +1,329 @@
+use std::io;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gouqi::Error;
+use log::warn;
+
+use crate::config::NetworkConfig;
+
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(500);
+const LONGEST_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// How many requests in a row, each having used up its retries, have to fail before
+/// Jira is left alone.
+const FAILURES_BEFORE_GIVING_UP: u32 = 3;
+const GIVE_UP_FOR: Duration = Duration::from_secs(60);
+
+/// Sits between the server and Jira, spreading requests out, retrying the ones that fail
+/// for reasons that may pass, and failing straight away while Jira keeps failing so that
+/// cached tickets are shown without waiting on it.
+///
+/// When Jira asks to be left alone for a while with `Retry-After`, every request waits
+/// that long, up to `GIVE_UP_FOR`, on top of the delay between retries.
+pub struct RequestGuard {
+    token_bucket: Mutex<TokenBucket>,
+    circuit_breaker: Mutex<CircuitBreaker>,
+    max_retries: u32,
+}
+
+impl RequestGuard {
+    pub fn new(network_config: &NetworkConfig) -> RequestGuard {
+        RequestGuard {
+            token_bucket: Mutex::new(TokenBucket::new(
+                network_config.requests_per_second,
+                Instant::now(),
+            )),
+            circuit_breaker: Mutex::new(CircuitBreaker::new()),
+            max_retries: network_config.max_retries,
+        }
+    }
+
+    /// For requests that can safely be made again, such as reading tickets.
+    pub fn call<T>(&self, request: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+        self.call_retrying(request, is_transient)
+    }
+
+    /// For requests that change a ticket, such as adding a comment. One that timed out or
+    /// failed on Jira's side may still have been applied, so it is only retried when Jira
+    /// turned it away for being rate limited.
+    pub fn call_once<T>(&self, request: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+        self.call_retrying(request, is_rate_limited)
+    }
+
+    fn call_retrying<T>(
+        &self,
+        mut request: impl FnMut() -> Result<T, Error>,
+        is_retried: fn(&Error) -> bool,
+    ) -> Result<T, Error> {
+        if let Some(retry_at) = self
+            .circuit_breaker
+            .lock()
+            .unwrap()
+            .blocked_until(Instant::now())
+        {
+            return Err(Error::IO(io::Error::other(format!(
+                "Jira keeps failing, not asking it again for {}s",
+                retry_at.saturating_duration_since(Instant::now()).as_secs()
+            ))));
+        }
+        let mut retries = 0;
+        loop {
+            self.wait_for_token();
+            let result = request();
+            if result.as_ref().is_err_and(is_retried) && retries < self.max_retries {
+                thread::sleep(retry_delay(retries));
+                retries += 1;
+                continue;
+            }
+            let mut circuit_breaker = self.circuit_breaker.lock().unwrap();
+            if result.as_ref().is_err_and(is_transient) {
+                if circuit_breaker.record_failure(Instant::now()) {
+                    warn!(
+                        "Jira failed {FAILURES_BEFORE_GIVING_UP} times in a row, using cached tickets for the next {}s",
+                        GIVE_UP_FOR.as_secs()
+                    );
+                }
+            } else {
+                // Errors such as a missing ticket still mean Jira is answering.
+                circuit_breaker.record_success();
+            }
+            return result;
+        }
+    }
+
+    pub fn hold_off(&self, retry_after: Duration) {
+        let now = Instant::now();
+        self.token_bucket
+            .lock()
+            .unwrap()
+            .hold_off_until(now + retry_after.min(GIVE_UP_FOR), now);
+    }
+
+    fn wait_for_token(&self) {
+        loop {
+            let wait = match self.token_bucket.lock().unwrap().take(Instant::now()) {
+                Ok(()) => return,
+                Err(wait) => wait,
+            };
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Whether the request could succeed if it were made again. A body that does not parse
+/// will not parse the next time either.
+fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::Http(_) | Error::IO(_) => true,
+        Error::Fault { code, .. } => code.as_u16() == 429 || code.is_server_error(),
+        _ => false,
+    }
+}
+
+fn is_rate_limited(error: &Error) -> bool {
+    matches!(error, Error::Fault { code, .. } if code.as_u16() == 429)
+}
+
+fn retry_delay(retries: u32) -> Duration {
+    FIRST_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(retries))
+        .min(LONGEST_RETRY_DELAY)
+}
+
+/// Lets through bursts of up to a second's worth of requests, refilling continuously.
+struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    tokens_per_second: f64,
+    refilled_at: Instant,
+    held_off_until: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn new(tokens_per_second: f64, now: Instant) -> TokenBucket {
+        let tokens_per_second = tokens_per_second.max(0.1);
+        let capacity = tokens_per_second.max(1.0);
+        TokenBucket {
+            tokens: capacity,
+            capacity,
+            tokens_per_second,
+            refilled_at: now,
+            held_off_until: None,
+        }
+    }
+
+    /// Hands out no tokens until then, starting from an empty bucket afterwards so that
+    /// the waiting requests do not all go at once.
+    fn hold_off_until(&mut self, held_off_until: Instant, now: Instant) {
+        self.tokens = 0.0;
+        self.held_off_until = Some(held_off_until.max(self.held_off_until.unwrap_or(now)));
+    }
+
+    /// Takes a token, or says how long until there will be one.
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        if let Some(held_off_until) = self.held_off_until {
+            if now < held_off_until {
+                return Err(held_off_until - now);
+            }
+            self.held_off_until = None;
+            self.refilled_at = held_off_until;
+        }
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.tokens_per_second).min(self.capacity);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - self.tokens) / self.tokens_per_second,
+        ))
+    }
+}
+
+/// Stops Jira being asked after repeated failures. Once `GIVE_UP_FOR` has passed requests
+/// are let through again, and the first of them to fail stops them straight away.
+struct CircuitBreaker {
+    consecutive_failures: u32,
+    blocked_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn new() -> CircuitBreaker {
+        CircuitBreaker {
+            consecutive_failures: 0,
+            blocked_until: None,
+        }
+    }
+
+    fn blocked_until(&self, now: Instant) -> Option<Instant> {
+        self.blocked_until
+            .filter(|blocked_until| now < *blocked_until)
+    }
+
+    fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.blocked_until = None;
+    }
+
+    /// Returns whether this failure is the one that stopped requests.
+    fn record_failure(&mut self, now: Instant) -> bool {
+        self.consecutive_failures += 1;
+        if self.consecutive_failures < FAILURES_BEFORE_GIVING_UP {
+            return false;
+        }
+        let was_blocked = self.blocked_until.is_some();
+        self.blocked_until = Some(now + GIVE_UP_FOR);
+        !was_blocked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket_spreads_out_requests_after_a_burst() {
+        let start = Instant::now();
+        let mut token_bucket = TokenBucket::new(2.0, start);
+
+        assert_eq!(token_bucket.take(start), Ok(()));
+        assert_eq!(token_bucket.take(start), Ok(()));
+        assert_eq!(token_bucket.take(start), Err(Duration::from_millis(500)));
+        assert_eq!(
+            token_bucket.take(start + Duration::from_millis(500)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn token_bucket_hands_out_nothing_while_held_off() {
+        let start = Instant::now();
+        let mut token_bucket = TokenBucket::new(2.0, start);
+
+        token_bucket.hold_off_until(start + Duration::from_secs(3), start);
+
+        assert_eq!(
+            token_bucket.take(start + Duration::from_secs(1)),
+            Err(Duration::from_secs(2))
+        );
+        assert_eq!(
+            token_bucket.take(start + Duration::from_secs(3)),
+            Err(Duration::from_millis(500))
+        );
+        assert_eq!(
+            token_bucket.take(start + Duration::from_millis(3500)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_a_limit() {
+        assert_eq!(retry_delay(0), Duration::from_millis(500));
+        assert_eq!(retry_delay(2), Duration::from_secs(2));
+        assert_eq!(retry_delay(20), LONGEST_RETRY_DELAY);
+    }
+
+    #[test]
+    fn circuit_breaker_blocks_after_repeated_failures_until_the_wait_is_over() {
+        let start = Instant::now();
+        let mut circuit_breaker = CircuitBreaker::new();
+
+        assert!(!circuit_breaker.record_failure(start));
+        assert!(!circuit_breaker.record_failure(start));
+        assert!(circuit_breaker.record_failure(start));
+        assert!(circuit_breaker.blocked_until(start).is_some());
+        assert!(circuit_breaker.blocked_until(start + GIVE_UP_FOR).is_none());
+
+        // The request let through afterwards failing blocks requests again straight away.
+        circuit_breaker.record_failure(start + GIVE_UP_FOR);
+        assert!(circuit_breaker.blocked_until(start + GIVE_UP_FOR).is_some());
+
+        circuit_breaker.record_success();
+        assert!(circuit_breaker.blocked_until(start).is_none());
+    }
+
+    #[test]
+    fn changes_that_may_have_been_applied_are_not_retried() {
+        let request_guard = RequestGuard::new(&NetworkConfig::default());
+        let mut requests = 0;
+
+        let result = request_guard.call_once(|| -> Result<(), Error> {
+            requests += 1;
+            Err(Error::IO(io::Error::other("timed out")))
+        });
+
+        assert!(matches!(result, Err(Error::IO(_))));
+        assert_eq!(requests, 1);
+    }
+
+    #[test]
+    fn missing_tickets_are_not_retried() {
+        let request_guard = RequestGuard::new(&NetworkConfig::default());
+        let mut requests = 0;
+
+        let result = request_guard.call(|| -> Result<(), Error> {
+            requests += 1;
+            Err(Error::NotFound)
+        });
+
+        assert!(matches!(result, Err(Error::NotFound)));
+        assert_eq!(requests, 1);
+    }
+
+    #[test]
+    fn bodies_that_do_not_parse_are_not_retried() {
+        let request_guard = RequestGuard::new(&NetworkConfig::default());
+        let mut requests = 0;
+
+        let result = request_guard.call(|| -> Result<(), Error> {
+            requests += 1;
+            Err(Error::Serde(serde::de::Error::custom("missing field")))
+        });
+
+        assert!(matches!(result, Err(Error::Serde(_))));
+        assert_eq!(requests, 1);
+    }
+}
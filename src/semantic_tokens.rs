@@ -0,0 +1,128 @@
+use lsp_types::{SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokensLegend};
+
+use crate::jira_resolver::JiraTicket;
+use crate::refrence_finder::InlineRange;
+
+/// Ticket keys get their own token type, so themes can colour them apart from the
+/// comments and strings they usually sit in.
+const JIRA_TICKET_TOKEN_TYPE: &str = "jiraTicket";
+
+const TO_DO_MODIFIER: u32 = 1 << 0;
+const IN_PROGRESS_MODIFIER: u32 = 1 << 1;
+const DONE_MODIFIER: u32 = 1 << 2;
+const ASSIGNED_TO_ME_MODIFIER: u32 = 1 << 3;
+const UNKNOWN_MODIFIER: u32 = 1 << 4;
+
+/// The order of the modifiers matches the bits above.
+pub fn semantic_tokens_legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: vec![SemanticTokenType::new(JIRA_TICKET_TOKEN_TYPE)],
+        token_modifiers: vec![
+            SemanticTokenModifier::new("toDo"),
+            SemanticTokenModifier::new("inProgress"),
+            SemanticTokenModifier::new("done"),
+            SemanticTokenModifier::new("assignedToMe"),
+            SemanticTokenModifier::new("unknown"),
+        ],
+    }
+}
+
+/// The modifiers of a ticket key, or of a key Jira has no ticket for.
+pub fn ticket_token_modifiers(ticket: Option<&JiraTicket>, is_assigned_to_me: bool) -> u32 {
+    let Some(ticket) = ticket else {
+        return UNKNOWN_MODIFIER;
+    };
+    let status_modifier = match ticket.status_category.as_str() {
+        "new" => TO_DO_MODIFIER,
+        "indeterminate" => IN_PROGRESS_MODIFIER,
+        "done" => DONE_MODIFIER,
+        _ => 0,
+    };
+    if is_assigned_to_me {
+        status_modifier | ASSIGNED_TO_ME_MODIFIER
+    } else {
+        status_modifier
+    }
+}
+
+/// Encodes ticket keys, given in document order, relative to one another as the
+/// specification requires.
+pub fn encode_ticket_tokens(
+    ticket_tokens: impl Iterator<Item = (InlineRange, u32)>,
+) -> Vec<SemanticToken> {
+    let mut previous_line = 0;
+    let mut previous_start = 0;
+    ticket_tokens
+        .map(|(range, modifiers)| {
+            let start = range.start_position();
+            let end = range.end_position();
+            let delta_line = start.line - previous_line;
+            let delta_start = if delta_line == 0 {
+                start.character - previous_start
+            } else {
+                start.character
+            };
+            previous_line = start.line;
+            previous_start = start.character;
+            SemanticToken {
+                delta_line,
+                delta_start,
+                length: end.character - start.character,
+                token_type: 0,
+                token_modifiers_bitset: modifiers,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::{Position, Range};
+
+    fn inline_range(line: u32, start: u32, end: u32) -> InlineRange {
+        InlineRange::try_from(Range::new(
+            Position::new(line, start),
+            Position::new(line, end),
+        ))
+        .ok()
+        .unwrap()
+    }
+
+    #[test]
+    fn encodes_tokens_relative_to_the_previous_one() {
+        let tokens = encode_ticket_tokens(
+            vec![
+                (inline_range(1, 4, 11), TO_DO_MODIFIER),
+                (inline_range(1, 20, 26), DONE_MODIFIER),
+                (inline_range(3, 2, 8), UNKNOWN_MODIFIER),
+            ]
+            .into_iter(),
+        );
+
+        let encoded: Vec<(u32, u32, u32, u32)> = tokens
+            .iter()
+            .map(|token| {
+                (
+                    token.delta_line,
+                    token.delta_start,
+                    token.length,
+                    token.token_modifiers_bitset,
+                )
+            })
+            .collect();
+        assert_eq!(
+            encoded,
+            vec![
+                (1, 4, 7, TO_DO_MODIFIER),
+                (0, 16, 6, DONE_MODIFIER),
+                (2, 2, 6, UNKNOWN_MODIFIER),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_tickets_are_only_marked_unknown() {
+        assert_eq!(ticket_token_modifiers(None, true), UNKNOWN_MODIFIER);
+    }
+}
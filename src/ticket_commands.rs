@@ -0,0 +1,187 @@
+use lsp_types::{ClientInfo, Command, ExecuteCommandParams, Location, Position, Uri};
+use serde::{Deserialize, Serialize};
+
+/// Commands run through `workspace/executeCommand`: changes to a ticket offered as
+/// code actions, and listing a ticket's refrences from its code lens in clients other
+/// than VS Code.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TicketCommand {
+    #[serde(rename_all = "camelCase")]
+    Transition {
+        key: String,
+        transition_id: String,
+    },
+    AssignToMe {
+        key: String,
+    },
+    AddComment {
+        key: String,
+        body: String,
+    },
+    ShowRefrences {
+        key: String,
+    },
+}
+
+pub const TRANSITION_TICKET_COMMAND: &str = "refrences-lsp.transitionTicket";
+pub const ASSIGN_TICKET_TO_ME_COMMAND: &str = "refrences-lsp.assignTicketToMe";
+pub const ADD_TICKET_COMMENT_COMMAND: &str = "refrences-lsp.addTicketComment";
+pub const SHOW_TICKET_REFRENCES_COMMAND: &str = "refrences-lsp.showTicketRefrences";
+/// A VS Code command run by the client rather than the server, which lists the locations
+/// next to the position in a peek view. Other clients, such as Neovim and Helix, do not
+/// know it.
+const SHOW_REFERENCES_COMMAND: &str = "editor.action.showReferences";
+/// How VS Code and its builds without the Microsoft branding name themselves.
+const VS_CODE_CLIENT_NAMES: [&str; 3] = ["Visual Studio Code", "VSCodium", "Code - OSS"];
+
+/// Every command the server can execute, to be advertised in its capabilities.
+pub const TICKET_COMMANDS: [&str; 4] = [
+    TRANSITION_TICKET_COMMAND,
+    ASSIGN_TICKET_TO_ME_COMMAND,
+    ADD_TICKET_COMMENT_COMMAND,
+    SHOW_TICKET_REFRENCES_COMMAND,
+];
+
+impl TicketCommand {
+    pub fn key(&self) -> &str {
+        match self {
+            TicketCommand::Transition { key, .. } => key,
+            TicketCommand::AssignToMe { key } => key,
+            TicketCommand::AddComment { key, .. } => key,
+            TicketCommand::ShowRefrences { key } => key,
+        }
+    }
+
+    fn command_name(&self) -> &'static str {
+        match self {
+            TicketCommand::Transition { .. } => TRANSITION_TICKET_COMMAND,
+            TicketCommand::AssignToMe { .. } => ASSIGN_TICKET_TO_ME_COMMAND,
+            TicketCommand::AddComment { .. } => ADD_TICKET_COMMENT_COMMAND,
+            TicketCommand::ShowRefrences { .. } => SHOW_TICKET_REFRENCES_COMMAND,
+        }
+    }
+
+    pub fn into_command(self, title: String) -> Command {
+        Command {
+            title,
+            command: self.command_name().to_owned(),
+            arguments: Some(vec![serde_json::to_value(&self).unwrap()]),
+        }
+    }
+
+    /// Reads back a command built by `into_command`.
+    pub fn from_execute_command_params(params: ExecuteCommandParams) -> Option<TicketCommand> {
+        if !TICKET_COMMANDS.contains(&params.command.as_str()) {
+            return None;
+        }
+        let argument = params.arguments.into_iter().next()?;
+        serde_json::from_value::<TicketCommand>(argument)
+            .ok()
+            .filter(|command| command.command_name() == params.command)
+    }
+}
+
+/// Whether the client runs `editor.action.showReferences`, rather than the server having
+/// to list the refrences through `TicketCommand::ShowRefrences`.
+pub fn client_shows_references(client_info: Option<&ClientInfo>) -> bool {
+    client_info.is_some_and(|client_info| {
+        VS_CODE_CLIENT_NAMES
+            .iter()
+            .any(|name| client_info.name.starts_with(name))
+    })
+}
+
+/// Lists a ticket's refrences from its code lens in VS Code.
+pub fn show_refrences_command(
+    title: String,
+    uri: &Uri,
+    position: Position,
+    locations: &[Location],
+) -> Command {
+    Command {
+        title,
+        command: SHOW_REFERENCES_COMMAND.to_owned(),
+        arguments: Some(vec![
+            serde_json::to_value(uri).unwrap(),
+            serde_json::to_value(position).unwrap(),
+            serde_json::to_value(locations).unwrap(),
+        ]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_round_trip_through_execute_params() {
+        let command = TicketCommand::Transition {
+            key: "AUTO-12".to_owned(),
+            transition_id: "31".to_owned(),
+        }
+        .into_command("Move AUTO-12 to Done".to_owned());
+
+        let parsed = TicketCommand::from_execute_command_params(ExecuteCommandParams {
+            command: command.command,
+            arguments: command.arguments.unwrap(),
+            work_done_progress_params: Default::default(),
+        });
+
+        assert!(matches!(
+            parsed,
+            Some(TicketCommand::Transition { key, transition_id }) if key == "AUTO-12" && transition_id == "31"
+        ));
+    }
+
+    #[test]
+    fn refrences_are_shown_by_the_client() {
+        let uri: Uri = "file:///src/main.rs".parse().unwrap();
+        let position = Position::new(3, 7);
+        let locations = vec![Location::new(uri.clone(), Default::default())];
+
+        let command = show_refrences_command("1 reference".to_owned(), &uri, position, &locations);
+
+        assert_eq!(command.command, "editor.action.showReferences");
+        assert_eq!(
+            command.arguments.unwrap(),
+            vec![
+                serde_json::json!("file:///src/main.rs"),
+                serde_json::json!({ "line": 3, "character": 7 }),
+                serde_json::to_value(&locations).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_vs_code_shows_references_itself() {
+        let client_info = |name: &str| ClientInfo {
+            name: name.to_owned(),
+            version: None,
+        };
+
+        assert!(client_shows_references(Some(&client_info(
+            "Visual Studio Code - Insiders"
+        ))));
+        assert!(client_shows_references(Some(&client_info("VSCodium"))));
+        assert!(!client_shows_references(Some(&client_info("Neovim"))));
+        assert!(!client_shows_references(Some(&client_info("helix"))));
+        assert!(!client_shows_references(None));
+    }
+
+    #[test]
+    fn mismatched_command_name_is_rejected() {
+        let command = TicketCommand::AssignToMe {
+            key: "AUTO-12".to_owned(),
+        }
+        .into_command("Assign AUTO-12 to me".to_owned());
+
+        let parsed = TicketCommand::from_execute_command_params(ExecuteCommandParams {
+            command: ADD_TICKET_COMMENT_COMMAND.to_owned(),
+            arguments: command.arguments.unwrap(),
+            work_done_progress_params: Default::default(),
+        });
+
+        assert!(parsed.is_none());
+    }
+}
@@ -0,0 +1,139 @@
+use lsp_types::{Diagnostic, DiagnosticSeverity, Range};
+
+use crate::config::{DiagnosticLevel, DiagnosticsConfig, JiraConfig};
+use crate::jira_resolver::TicketLookup;
+
+const DIAGNOSTIC_SOURCE: &str = "jira";
+
+/// The diagnostic for a refrence to `key`, if its ticket is one the user wants flagged.
+pub fn diagnostic_for_refrence(
+    diagnostics_config: &DiagnosticsConfig,
+    jira_config: &JiraConfig,
+    key: &str,
+    range: Range,
+    lookup: &TicketLookup,
+) -> Option<Diagnostic> {
+    let (level, message) = match lookup {
+        TicketLookup::Found(ticket) => (
+            diagnostics_config.severity_for_status(&ticket.status, &ticket.status_category),
+            format!("{key} is {}: {}", ticket.status, ticket.title),
+        ),
+        TicketLookup::NotFound | TicketLookup::Forbidden if !jira_config.lists_project(key) => {
+            return None
+        }
+        TicketLookup::NotFound => (
+            diagnostics_config.missing_ticket,
+            format!("{key} does not exist, or you do not have permission to see it"),
+        ),
+        TicketLookup::Forbidden => (
+            diagnostics_config.missing_ticket,
+            format!("You do not have permission to see {key}"),
+        ),
+    };
+    Some(Diagnostic {
+        range,
+        severity: Some(diagnostic_severity(level)?),
+        source: Some(DIAGNOSTIC_SOURCE.to_owned()),
+        message,
+        ..Default::default()
+    })
+}
+
+fn diagnostic_severity(level: DiagnosticLevel) -> Option<DiagnosticSeverity> {
+    match level {
+        DiagnosticLevel::Off => None,
+        DiagnosticLevel::Hint => Some(DiagnosticSeverity::HINT),
+        DiagnosticLevel::Information => Some(DiagnosticSeverity::INFORMATION),
+        DiagnosticLevel::Warning => Some(DiagnosticSeverity::WARNING),
+        DiagnosticLevel::Error => Some(DiagnosticSeverity::ERROR),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::jira_resolver::JiraTicket;
+
+    fn config(projects: &str) -> Config {
+        toml::from_str(&format!(
+            r#"
+            [jira]
+            host = "https://example.atlassian.net"
+            email = "me@example.com"
+            api_token = "token"
+            projects = {projects}
+            "#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn flags_done_tickets() {
+        let config = config("[]");
+        let lookup = TicketLookup::Found(Box::new(
+            JiraTicket::test_ticket("AUTO-12").with_status("Closed", "done"),
+        ));
+
+        let diagnostic = diagnostic_for_refrence(
+            &config.diagnostics,
+            &config.jira,
+            "AUTO-12",
+            Range::default(),
+            &lookup,
+        )
+        .unwrap();
+
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(diagnostic.message, "AUTO-12 is Closed: Fix login bug");
+    }
+
+    #[test]
+    fn ignores_tickets_still_in_progress() {
+        let config = config("[]");
+        let lookup = TicketLookup::Found(Box::new(
+            JiraTicket::test_ticket("AUTO-12").with_status("In Progress", "indeterminate"),
+        ));
+
+        let diagnostic = diagnostic_for_refrence(
+            &config.diagnostics,
+            &config.jira,
+            "AUTO-12",
+            Range::default(),
+            &lookup,
+        );
+
+        assert!(diagnostic.is_none());
+    }
+
+    #[test]
+    fn flags_missing_tickets_of_listed_projects() {
+        let config = config(r#"["AUTO"]"#);
+
+        let diagnostic = diagnostic_for_refrence(
+            &config.diagnostics,
+            &config.jira,
+            "AUTO-404",
+            Range::default(),
+            &TicketLookup::NotFound,
+        )
+        .unwrap();
+
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::HINT));
+    }
+
+    #[test]
+    fn ignores_missing_keys_outside_listed_projects() {
+        let config = config("[]");
+
+        let diagnostic = diagnostic_for_refrence(
+            &config.diagnostics,
+            &config.jira,
+            "SHA-256",
+            Range::default(),
+            &TicketLookup::NotFound,
+        );
+
+        assert!(diagnostic.is_none());
+    }
+}
@@ -0,0 +1,105 @@
+use std::str::FromStr;
+
+use lsp_types::{request::Request, Uri};
+use serde::{Deserialize, Serialize};
+
+use crate::atlassian_markup_transpiler::transpile_atlassian_markup_to_markdown;
+use crate::jira_resolver::JiraTicket;
+
+/// Tickets are opened as read-only virtual documents under this scheme,
+/// e.g. `jira://example.atlassian.net/ABC-123`.
+pub const JIRA_SCHEME: &str = "jira";
+
+/// Requests the contents of a virtual document, such as a ticket opened by go-to-definition.
+/// Mirrors `workspace/textDocumentContent` from version 3.18 of the specification,
+/// which `lsp_types` does not yet provide.
+pub enum TextDocumentContentRequest {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TextDocumentContentParams {
+    pub uri: Uri,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TextDocumentContentResult {
+    pub text: String,
+}
+
+impl Request for TextDocumentContentRequest {
+    type Params = TextDocumentContentParams;
+    type Result = TextDocumentContentResult;
+    const METHOD: &'static str = "workspace/textDocumentContent";
+}
+
+pub fn ticket_document_uri(jira_host: &str, key: &str) -> Uri {
+    let authority = jira_host
+        .split_once("://")
+        .map_or(jira_host, |(_, authority)| authority)
+        .trim_end_matches('/');
+    Uri::from_str(&format!("{JIRA_SCHEME}://{authority}/{key}"))
+        .expect("ticket keys are always valid uri paths")
+}
+
+/// The key of the ticket a `jira://` document shows, or `None` for any other document.
+/// The key is the last segment of the path, after the context path of hosts such as
+/// `https://example.com/jira`.
+pub fn ticket_key_from_document_uri(uri: &Uri) -> Option<&str> {
+    if uri.scheme().map(|scheme| scheme.as_str()) != Some(JIRA_SCHEME) {
+        return None;
+    }
+    uri.path()
+        .as_str()
+        .rsplit('/')
+        .next()
+        .filter(|key| !key.is_empty())
+}
+
+pub fn render_ticket_document(ticket: &JiraTicket) -> String {
+    format!(
+        r"# {}: {}
+
+**Status:** {}
+**Assignee:** {}
+**Updated:** {}
+
+{}
+---
+
+{}",
+        ticket.key,
+        ticket.title,
+        ticket.status,
+        ticket.assignee.as_deref().unwrap_or("Unassigned"),
+        ticket.updated.as_deref().unwrap_or("Never"),
+        ticket.details(),
+        transpile_atlassian_markup_to_markdown(&ticket.description),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticket_uri_round_trips() {
+        let uri = ticket_document_uri("https://example.atlassian.net/", "AUTO-12");
+
+        assert_eq!(uri.as_str(), "jira://example.atlassian.net/AUTO-12");
+        assert_eq!(ticket_key_from_document_uri(&uri), Some("AUTO-12"));
+    }
+
+    #[test]
+    fn context_paths_are_not_part_of_the_key() {
+        let uri = ticket_document_uri("https://example.com/jira", "AUTO-12");
+
+        assert_eq!(uri.as_str(), "jira://example.com/jira/AUTO-12");
+        assert_eq!(ticket_key_from_document_uri(&uri), Some("AUTO-12"));
+    }
+
+    #[test]
+    fn other_schemes_are_not_tickets() {
+        let uri = Uri::from_str("file:///tmp/AUTO-12").unwrap();
+
+        assert_eq!(ticket_key_from_document_uri(&uri), None);
+    }
+}
@@ -0,0 +1,83 @@
+use lsp_types::{Range, SymbolKind, TypeHierarchyItem};
+
+use crate::jira_resolver::{JiraTicket, LinkedTicket};
+use crate::ticket_document::ticket_document_uri;
+
+/// A ticket in the epic and subtask tree browsed through `typeHierarchy/*`. Opening it
+/// shows the ticket as a `jira://` document.
+pub fn type_hierarchy_item(jira_host: &str, ticket: &JiraTicket) -> TypeHierarchyItem {
+    hierarchy_item(
+        jira_host,
+        &ticket.key,
+        &ticket.title,
+        &ticket.status,
+        ticket.is_epic(),
+    )
+}
+
+pub fn linked_type_hierarchy_item(
+    jira_host: &str,
+    linked_ticket: &LinkedTicket,
+) -> TypeHierarchyItem {
+    hierarchy_item(
+        jira_host,
+        &linked_ticket.key,
+        &linked_ticket.title,
+        &linked_ticket.status,
+        linked_ticket.is_epic(),
+    )
+}
+
+fn hierarchy_item(
+    jira_host: &str,
+    key: &str,
+    title: &str,
+    status: &str,
+    is_epic: bool,
+) -> TypeHierarchyItem {
+    TypeHierarchyItem {
+        name: key.to_owned(),
+        kind: if is_epic {
+            SymbolKind::NAMESPACE
+        } else {
+            SymbolKind::KEY
+        },
+        tags: None,
+        detail: Some(format!("{title} ({status})")),
+        uri: ticket_document_uri(jira_host, key),
+        range: Range::default(),
+        selection_range: Range::default(),
+        data: Some(serde_json::Value::String(key.to_owned())),
+    }
+}
+
+/// The key of the ticket an item handed back by the client is for.
+pub fn ticket_key_from_item(item: &TypeHierarchyItem) -> &str {
+    item.data
+        .as_ref()
+        .and_then(|data| data.as_str())
+        .unwrap_or(&item.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_know_their_ticket() {
+        let ticket = JiraTicket {
+            key: "AUTO-1".to_owned(),
+            title: "Login revamp".to_owned(),
+            status: "In Progress".to_owned(),
+            issue_type: Some("Epic".to_owned()),
+            ..Default::default()
+        };
+
+        let item = type_hierarchy_item("https://example.atlassian.net", &ticket);
+
+        assert_eq!(ticket_key_from_item(&item), "AUTO-1");
+        assert_eq!(item.kind, SymbolKind::NAMESPACE);
+        assert_eq!(item.detail.as_deref(), Some("Login revamp (In Progress)"));
+        assert_eq!(item.uri.as_str(), "jira://example.atlassian.net/AUTO-1");
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::jira_resolver::{ChildProgress, JiraTicket, TicketComment, TicketLookup};
+
+#[derive(Serialize, Deserialize)]
+struct StoredLookup {
+    lookup: TicketLookup,
+    fetched_at: SystemTime,
+}
+
+/// The tickets fetched from Jira so far, keyed by issue key. Keys Jira had no visible
+/// ticket for are kept too, so that they are not asked about again and again.
+///
+/// Lookups older than the TTL are fetched again, but kept until that succeeds, so that
+/// they can still be shown while Jira can not be reached.
+pub struct TicketStore {
+    tickets: HashMap<String, StoredLookup>,
+    ttl: Duration,
+    cache_file: Option<PathBuf>,
+}
+
+impl TicketStore {
+    pub fn new(ttl: Duration, cache_file: Option<PathBuf>) -> TicketStore {
+        TicketStore {
+            tickets: HashMap::new(),
+            ttl,
+            cache_file,
+        }
+    }
+
+    /// Starts off with the tickets cached by a previous session, if there are any.
+    pub fn load(ttl: Duration, cache_file: Option<PathBuf>) -> TicketStore {
+        let mut ticket_store = TicketStore::new(ttl, cache_file);
+        let Some(cache_file) = &ticket_store.cache_file else {
+            return ticket_store;
+        };
+        let Ok(contents) = fs::read_to_string(cache_file) else {
+            return ticket_store;
+        };
+        match serde_json::from_str(&contents) {
+            Ok(tickets) => {
+                ticket_store.tickets = tickets;
+                info!(
+                    "Loaded {} cached tickets from {}",
+                    ticket_store.tickets.len(),
+                    cache_file.display()
+                );
+            }
+            Err(e) => warn!("Ignoring the ticket cache because {e}"),
+        }
+        ticket_store
+    }
+
+    /// Writes the tickets to the cache file, replacing it as a whole so that a session
+    /// starting meanwhile never reads half of it.
+    pub fn save(&self) {
+        let Some(cache_file) = &self.cache_file else {
+            return;
+        };
+        let partial_file = cache_file.with_extension("json.partial");
+        let result = cache_file
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&partial_file, serde_json::to_vec(&self.tickets)?))
+            .and_then(|_| fs::rename(&partial_file, cache_file));
+        if let Err(e) = result {
+            warn!(
+                "Could not cache tickets in {} because {e}",
+                cache_file.display()
+            );
+        }
+    }
+
+    /// `None` when the key has not been looked up yet.
+    pub fn get(&self, key: &str) -> Option<&TicketLookup> {
+        self.tickets.get(key).map(|stored| &stored.lookup)
+    }
+
+    pub fn ticket(&self, key: &str) -> Option<&JiraTicket> {
+        match self.get(key) {
+            Some(TicketLookup::Found(ticket)) => Some(ticket),
+            _ => None,
+        }
+    }
+
+    pub fn tickets(&self) -> impl Iterator<Item = &JiraTicket> {
+        self.tickets
+            .values()
+            .filter_map(|stored| match &stored.lookup {
+                TicketLookup::Found(ticket) => Some(ticket.as_ref()),
+                _ => None,
+            })
+    }
+
+    /// How long ago the ticket was fetched, if that is longer ago than the TTL.
+    pub fn stale_for(&self, key: &str) -> Option<Duration> {
+        let age = self.tickets.get(key)?.fetched_at.elapsed().ok()?;
+        (age > self.ttl).then_some(age)
+    }
+
+    /// The keys that have not been looked up yet, or not within the TTL, each once and
+    /// in order.
+    pub fn keys_to_fetch<'a>(&self, keys: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+        keys.into_iter()
+            .filter(|key| !self.tickets.contains_key(*key) || self.stale_for(key).is_some())
+            .collect::<BTreeSet<&str>>()
+            .into_iter()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// The keys of the tickets found so far, to keep up to date.
+    pub fn tracked_keys(&self) -> Vec<String> {
+        self.tickets
+            .iter()
+            .filter(|(_, stored)| matches!(stored.lookup, TicketLookup::Found(_)))
+            .map(|(key, _)| key.to_owned())
+            .collect()
+    }
+
+    /// Since when changes to the tracked tickets may have been missed: the last time they
+    /// were all fetched or synced.
+    pub fn last_synced_at(&self) -> Option<SystemTime> {
+        self.tickets
+            .values()
+            .filter(|stored| matches!(stored.lookup, TicketLookup::Found(_)))
+            .map(|stored| stored.fetched_at)
+            .min()
+    }
+
+    /// Takes in the tickets that changed since the last sync, and counts every tracked
+    /// ticket as fetched when the sync started.
+    pub fn apply_sync(&mut self, synced_at: SystemTime, updated_tickets: Vec<JiraTicket>) {
+        for stored in self.tickets.values_mut() {
+            if matches!(stored.lookup, TicketLookup::Found(_)) {
+                stored.fetched_at = synced_at;
+            }
+        }
+        self.tickets
+            .extend(updated_tickets.into_iter().map(|ticket| {
+                (
+                    ticket.key.to_owned(),
+                    StoredLookup {
+                        lookup: TicketLookup::Found(Box::new(ticket)),
+                        fetched_at: synced_at,
+                    },
+                )
+            }));
+    }
+
+    pub fn extend(&mut self, lookups: impl IntoIterator<Item = (String, TicketLookup)>) {
+        let fetched_at = SystemTime::now();
+        self.tickets.extend(
+            lookups
+                .into_iter()
+                .map(|(key, lookup)| (key, StoredLookup { lookup, fetched_at })),
+        );
+    }
+
+    /// Keeps the comments with the ticket until it is next fetched, which it is whenever
+    /// it changes, such as by being commented on.
+    pub fn set_comments(&mut self, key: &str, comments: Vec<TicketComment>) {
+        if let Some(ticket) = self.ticket_mut(key) {
+            ticket.comments = Some(comments);
+        }
+    }
+
+    /// Keeps the progress of an epic with the ticket, which is either the epic itself or
+    /// one of its children, until the ticket is next fetched.
+    pub fn set_child_progress(&mut self, key: &str, epic_key: &str, progress: ChildProgress) {
+        let Some(ticket) = self.ticket_mut(key) else {
+            return;
+        };
+        if ticket.key == epic_key {
+            ticket.child_progress = Some(progress);
+        } else if let Some(parent) = ticket
+            .parent
+            .as_mut()
+            .filter(|parent| parent.key == epic_key)
+        {
+            parent.child_progress = Some(progress);
+        }
+    }
+
+    fn ticket_mut(&mut self, key: &str) -> Option<&mut JiraTicket> {
+        match self.tickets.get_mut(key) {
+            Some(StoredLookup {
+                lookup: TicketLookup::Found(ticket),
+                ..
+            }) => Some(ticket),
+            _ => None,
+        }
+    }
+
+    /// Drops what is known about the ticket, e.g. after changing it, so it is fetched again.
+    pub fn forget(&mut self, key: &str) {
+        self.tickets.remove(key);
+    }
+}
+
+/// Roughly how long ago something happened, e.g. `5 minutes`.
+pub fn describe_age(age: Duration) -> String {
+    let minutes = age.as_secs() / 60;
+    let (amount, unit) = match minutes {
+        0..60 => (minutes, "minute"),
+        60..1440 => (minutes / 60, "hour"),
+        _ => (minutes / 1440, "day"),
+    };
+    if amount == 1 {
+        format!("{amount} {unit}")
+    } else {
+        format!("{amount} {unit}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60 * 60);
+
+    #[test]
+    fn missing_tickets_are_not_looked_up_again() {
+        let mut ticket_store = TicketStore::new(TTL, None);
+        ticket_store.extend([
+            ("AUTO-404".to_owned(), TicketLookup::NotFound),
+            ("SECRET-1".to_owned(), TicketLookup::Forbidden),
+        ]);
+
+        let keys_to_fetch =
+            ticket_store.keys_to_fetch(["AUTO-2", "AUTO-404", "SECRET-1", "AUTO-1", "AUTO-2"]);
+
+        assert_eq!(keys_to_fetch, vec!["AUTO-1", "AUTO-2"]);
+        assert!(ticket_store.ticket("AUTO-404").is_none());
+    }
+
+    #[test]
+    fn forgotten_tickets_are_unknown_again() {
+        let mut ticket_store = TicketStore::new(TTL, None);
+        ticket_store.extend([("AUTO-404".to_owned(), TicketLookup::NotFound)]);
+
+        ticket_store.forget("AUTO-404");
+
+        assert_eq!(ticket_store.keys_to_fetch(["AUTO-404"]), vec!["AUTO-404"]);
+    }
+
+    #[test]
+    fn lookups_older_than_the_ttl_are_stale() {
+        let mut ticket_store = TicketStore::new(TTL, None);
+        ticket_store.tickets.insert(
+            "AUTO-404".to_owned(),
+            StoredLookup {
+                lookup: TicketLookup::NotFound,
+                fetched_at: SystemTime::now() - 2 * TTL,
+            },
+        );
+
+        assert_eq!(ticket_store.keys_to_fetch(["AUTO-404"]), vec!["AUTO-404"]);
+        assert!(ticket_store.get("AUTO-404").is_some());
+        assert!(ticket_store.stale_for("AUTO-404").is_some());
+    }
+
+    #[test]
+    fn syncing_refreshes_every_tracked_ticket() {
+        let mut ticket_store = TicketStore::new(TTL, None);
+        let fetched_at = SystemTime::now() - 2 * TTL;
+        for (key, lookup) in [
+            (
+                "AUTO-1",
+                TicketLookup::Found(Box::new(JiraTicket::test_ticket("AUTO-1"))),
+            ),
+            (
+                "AUTO-2",
+                TicketLookup::Found(Box::new(JiraTicket::test_ticket("AUTO-2"))),
+            ),
+            ("AUTO-404", TicketLookup::NotFound),
+        ] {
+            ticket_store
+                .tickets
+                .insert(key.to_owned(), StoredLookup { lookup, fetched_at });
+        }
+        assert_eq!(ticket_store.last_synced_at(), Some(fetched_at));
+
+        let synced_at = SystemTime::now();
+        ticket_store.apply_sync(
+            synced_at,
+            vec![JiraTicket::test_ticket("AUTO-2").with_status("Done", "done")],
+        );
+
+        assert_eq!(ticket_store.last_synced_at(), Some(synced_at));
+        assert_eq!(ticket_store.ticket("AUTO-2").unwrap().status, "Done");
+        assert_eq!(
+            ticket_store.keys_to_fetch(["AUTO-1", "AUTO-404"]),
+            vec!["AUTO-404"]
+        );
+    }
+
+    #[test]
+    fn comments_are_kept_until_the_ticket_changes() {
+        let mut ticket_store = TicketStore::new(TTL, None);
+        ticket_store.extend([(
+            "AUTO-1".to_owned(),
+            TicketLookup::Found(Box::new(JiraTicket::test_ticket("AUTO-1"))),
+        )]);
+        let comments = vec![TicketComment {
+            author: "Ada Lovelace".to_owned(),
+            created: None,
+            body: "Looking into it".to_owned(),
+        }];
+
+        ticket_store.set_comments("AUTO-1", comments.to_owned());
+        ticket_store.set_comments("AUTO-404", comments.to_owned());
+
+        assert_eq!(
+            ticket_store.ticket("AUTO-1").unwrap().comments,
+            Some(comments)
+        );
+        assert!(ticket_store.get("AUTO-404").is_none());
+
+        ticket_store.apply_sync(
+            SystemTime::now(),
+            vec![JiraTicket::test_ticket("AUTO-1").with_status("Done", "done")],
+        );
+        assert_eq!(ticket_store.ticket("AUTO-1").unwrap().comments, None);
+    }
+
+    #[test]
+    fn tickets_survive_a_restart() {
+        let cache_file = std::env::temp_dir().join(format!(
+            "refrences-lsp-cache-{}/tickets.json",
+            std::process::id()
+        ));
+        let mut ticket_store = TicketStore::new(TTL, Some(cache_file.to_owned()));
+        ticket_store.extend([("AUTO-404".to_owned(), TicketLookup::NotFound)]);
+        ticket_store.save();
+
+        let ticket_store = TicketStore::load(TTL, Some(cache_file.to_owned()));
+        fs::remove_dir_all(cache_file.parent().unwrap()).unwrap();
+
+        assert!(matches!(
+            ticket_store.get("AUTO-404"),
+            Some(TicketLookup::NotFound)
+        ));
+        assert!(ticket_store.stale_for("AUTO-404").is_none());
+    }
+
+    #[test]
+    fn ages_are_described_in_the_largest_unit() {
+        assert_eq!(describe_age(Duration::from_secs(30)), "0 minutes");
+        assert_eq!(describe_age(Duration::from_secs(60)), "1 minute");
+        assert_eq!(describe_age(Duration::from_secs(3 * 60 * 60)), "3 hours");
+        assert_eq!(
+            describe_age(Duration::from_secs(2 * 24 * 60 * 60)),
+            "2 days"
+        );
+    }
+}
@@ -0,0 +1,610 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::atlassian_markup_transpiler::transpile_atlassian_markup_to_markdown;
+use crate::config::CustomFieldType;
+use crate::jira_resolver::{
+    browse_url, ChildProgress, CustomFieldValue, JiraTicket, LinkedTicket, TicketComment,
+};
+use crate::ticket_store::describe_age;
+
+/// How a ticket is laid out in hovers, inlay hints and their tooltips.
+///
+/// - `{title}` is replaced by the field, and `{assignee|Unassigned}` falls back to the
+///   text after the `|` when the field is empty.
+/// - `{?due}...{/due}` only shows its contents when the field is not empty, and
+///   `{!due}...{/due}` only when it is.
+/// - `{icon:status}` is the icon of the configured icon set.
+/// - `{custom:Story points}` is the custom field configured with that name, empty for
+///   names that are not configured.
+/// - `{{` and `}}` are literal braces.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub struct TicketTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Field {
+        field: Field,
+        fallback: Option<String>,
+    },
+    Icon(Icon),
+    Conditional {
+        field: Field,
+        shown_when_empty: bool,
+        parts: Vec<Part>,
+    },
+}
+
+/// Everything a template can refer to.
+#[derive(Clone, Debug, PartialEq)]
+enum Field {
+    Key,
+    Title,
+    Description,
+    Status,
+    StatusCategory,
+    Assignee,
+    AssigneeEmail,
+    Reporter,
+    Priority,
+    IssueType,
+    Created,
+    Updated,
+    Due,
+    Labels,
+    Components,
+    FixVersions,
+    /// The most recent comments, once fetched for a hover.
+    Comments,
+    /// The parent, issue links and subtasks, as a Markdown list of links.
+    Related,
+    Parent,
+    Links,
+    Subtasks,
+    /// How many children of an epic are done, once counted for a hover.
+    Progress,
+    /// Every detail the ticket has, as a Markdown list.
+    Details,
+    Url,
+    /// A custom field, by its configured name.
+    Custom(String),
+    /// The custom fields of markup, each as a section headed by its name.
+    Sections,
+    /// How long ago the ticket was fetched, when Jira could not be reached since.
+    Stale,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        Some(match name {
+            "key" => Field::Key,
+            "title" => Field::Title,
+            "description" => Field::Description,
+            "status" => Field::Status,
+            "status_category" => Field::StatusCategory,
+            "assignee" => Field::Assignee,
+            "assignee_email" => Field::AssigneeEmail,
+            "reporter" => Field::Reporter,
+            "priority" => Field::Priority,
+            "issue_type" => Field::IssueType,
+            "created" => Field::Created,
+            "updated" => Field::Updated,
+            "due" => Field::Due,
+            "labels" => Field::Labels,
+            "components" => Field::Components,
+            "fix_versions" => Field::FixVersions,
+            "comments" => Field::Comments,
+            "related" => Field::Related,
+            "parent" => Field::Parent,
+            "links" => Field::Links,
+            "subtasks" => Field::Subtasks,
+            "progress" => Field::Progress,
+            "details" => Field::Details,
+            "url" => Field::Url,
+            "sections" => Field::Sections,
+            "stale" => Field::Stale,
+            _ => return Some(Field::Custom(name.strip_prefix("custom:")?.to_owned())),
+        })
+    }
+
+    fn value(&self, context: &TemplateContext) -> String {
+        let ticket = context.ticket;
+        let optional = |value: &Option<String>| value.to_owned().unwrap_or_default();
+        match self {
+            Field::Key => ticket.key.to_owned(),
+            Field::Title => ticket.title.to_owned(),
+            Field::Description => transpile_atlassian_markup_to_markdown(&ticket.description),
+            Field::Status => ticket.status.to_owned(),
+            Field::StatusCategory => ticket.status_category.to_owned(),
+            Field::Assignee => optional(&ticket.assignee),
+            Field::AssigneeEmail => optional(&ticket.assignee_email),
+            Field::Reporter => optional(&ticket.reporter),
+            Field::Priority => optional(&ticket.priority),
+            Field::IssueType => optional(&ticket.issue_type),
+            Field::Created => optional(&ticket.created),
+            Field::Updated => optional(&ticket.updated),
+            Field::Due => optional(&ticket.due),
+            Field::Labels => ticket.labels.join(", "),
+            Field::Components => ticket.components.join(", "),
+            Field::FixVersions => ticket.fix_versions.join(", "),
+            Field::Comments => ticket
+                .comments
+                .as_deref()
+                .map_or(String::new(), render_comments),
+            Field::Related => render_linked_tickets(
+                ticket
+                    .parent
+                    .iter()
+                    .chain(&ticket.links)
+                    .chain(&ticket.subtasks),
+                context.jira_host,
+            ),
+            Field::Parent => render_linked_tickets(&ticket.parent, context.jira_host),
+            Field::Links => render_linked_tickets(&ticket.links, context.jira_host),
+            Field::Subtasks => render_linked_tickets(&ticket.subtasks, context.jira_host),
+            Field::Progress => ticket
+                .child_progress
+                .map_or(String::new(), describe_progress),
+            Field::Details => ticket.details(),
+            Field::Url => browse_url(context.jira_host, &ticket.key),
+            Field::Custom(name) => ticket
+                .custom_fields
+                .iter()
+                .find(|custom_field| custom_field.name == *name)
+                .map_or(String::new(), custom_field_markdown),
+            Field::Sections => ticket
+                .custom_fields
+                .iter()
+                .filter(|custom_field| custom_field.field_type == CustomFieldType::Markup)
+                .map(|custom_field| {
+                    format!(
+                        "### {}\n\n{}",
+                        custom_field.name,
+                        custom_field_markdown(custom_field)
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Field::Stale => context.stale_for.map(describe_age).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Icon {
+    Status,
+    Date,
+    Assignee,
+    Reporter,
+    Priority,
+    IssueType,
+    Labels,
+}
+
+impl Icon {
+    fn from_name(name: &str) -> Option<Icon> {
+        Some(match name {
+            "status" => Icon::Status,
+            "date" => Icon::Date,
+            "assignee" => Icon::Assignee,
+            "reporter" => Icon::Reporter,
+            "priority" => Icon::Priority,
+            "issue_type" => Icon::IssueType,
+            "labels" => Icon::Labels,
+            _ => return None,
+        })
+    }
+}
+
+/// Nerd Font glyphs only show in terminals using a patched font, elsewhere they are
+/// drawn as empty boxes.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IconSet {
+    #[default]
+    NerdFont,
+    Ascii,
+}
+
+impl IconSet {
+    fn icon(self, icon: Icon) -> &'static str {
+        match (self, icon) {
+            (IconSet::NerdFont, Icon::Status) => "\u{f15ab}",
+            (IconSet::NerdFont, Icon::Date) => "\u{f00ed}",
+            (IconSet::NerdFont, Icon::Assignee) => "\u{f2bd}",
+            (IconSet::NerdFont, Icon::Reporter) => "\u{f007}",
+            (IconSet::NerdFont, Icon::Priority) => "\u{f024}",
+            (IconSet::NerdFont, Icon::IssueType) => "\u{f02b}",
+            (IconSet::NerdFont, Icon::Labels) => "\u{f02c}",
+            (IconSet::Ascii, Icon::Status) => "[*]",
+            (IconSet::Ascii, Icon::Date) => "[d]",
+            (IconSet::Ascii, Icon::Assignee) => "[@]",
+            (IconSet::Ascii, Icon::Reporter) => "[r]",
+            (IconSet::Ascii, Icon::Priority) => "[!]",
+            (IconSet::Ascii, Icon::IssueType) => "[t]",
+            (IconSet::Ascii, Icon::Labels) => "[#]",
+        }
+    }
+}
+
+/// What a template is filled in from.
+pub struct TemplateContext<'a> {
+    pub ticket: &'a JiraTicket,
+    /// For links to the ticket and the tickets it mentions.
+    pub jira_host: &'a str,
+    pub stale_for: Option<Duration>,
+    pub icon_set: IconSet,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TemplateError {
+    #[error("`{{{0}}}` is not a ticket field")]
+    UnknownField(String),
+    #[error("`{{icon:{0}}}` is not an icon")]
+    UnknownIcon(String),
+    #[error("`{{/{0}}}` does not close the last opened `{{?{0}}}` or `{{!{0}}}`")]
+    UnexpectedClose(String),
+    #[error("`{{?{0}}}` or `{{!{0}}}` is never closed with `{{/{0}}}`")]
+    UnclosedConditional(String),
+    #[error("a `{{` is never closed, write `{{{{` for a literal one")]
+    UnclosedPlaceholder,
+    #[error("a `}}` is never opened, write `}}}}` for a literal one")]
+    UnopenedPlaceholder,
+}
+
+impl TicketTemplate {
+    pub fn parse(template: &str) -> Result<TicketTemplate, TemplateError> {
+        // The conditionals still open, innermost last, with the parts before each.
+        let mut open_conditionals: Vec<(String, bool, Vec<Part>)> = Vec::new();
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut characters = template.chars().peekable();
+        while let Some(character) = characters.next() {
+            match character {
+                '{' if characters.peek() == Some(&'{') => {
+                    characters.next();
+                    text.push('{');
+                }
+                '}' if characters.peek() == Some(&'}') => {
+                    characters.next();
+                    text.push('}');
+                }
+                '}' => return Err(TemplateError::UnopenedPlaceholder),
+                '{' => {
+                    let mut tag = String::new();
+                    loop {
+                        match characters.next() {
+                            Some('}') => break,
+                            Some(character) => tag.push(character),
+                            None => return Err(TemplateError::UnclosedPlaceholder),
+                        }
+                    }
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    if let Some(name) = tag.strip_prefix('?').or_else(|| tag.strip_prefix('!')) {
+                        field_named(name)?;
+                        open_conditionals.push((
+                            name.to_owned(),
+                            tag.starts_with('!'),
+                            std::mem::take(&mut parts),
+                        ));
+                    } else if let Some(name) = tag.strip_prefix('/') {
+                        match open_conditionals.pop() {
+                            Some((open_name, shown_when_empty, outer_parts))
+                                if open_name == name =>
+                            {
+                                let conditional = Part::Conditional {
+                                    field: field_named(name)?,
+                                    shown_when_empty,
+                                    parts: std::mem::replace(&mut parts, outer_parts),
+                                };
+                                parts.push(conditional);
+                            }
+                            _ => return Err(TemplateError::UnexpectedClose(name.to_owned())),
+                        }
+                    } else if let Some(name) = tag.strip_prefix("icon:") {
+                        let icon = Icon::from_name(name)
+                            .ok_or_else(|| TemplateError::UnknownIcon(name.to_owned()))?;
+                        parts.push(Part::Icon(icon));
+                    } else {
+                        let (name, fallback) = match tag.split_once('|') {
+                            Some((name, fallback)) => (name, Some(fallback.to_owned())),
+                            None => (tag.as_str(), None),
+                        };
+                        parts.push(Part::Field {
+                            field: field_named(name)?,
+                            fallback,
+                        });
+                    }
+                }
+                character => text.push(character),
+            }
+        }
+        if let Some((name, _, _)) = open_conditionals.pop() {
+            return Err(TemplateError::UnclosedConditional(name));
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(TicketTemplate { parts })
+    }
+
+    pub fn render(&self, context: &TemplateContext) -> String {
+        let mut rendered = String::new();
+        render_parts(&self.parts, context, &mut rendered);
+        rendered
+    }
+}
+
+impl TryFrom<String> for TicketTemplate {
+    type Error = TemplateError;
+
+    fn try_from(template: String) -> Result<Self, Self::Error> {
+        TicketTemplate::parse(&template)
+    }
+}
+
+fn field_named(name: &str) -> Result<Field, TemplateError> {
+    Field::from_name(name).ok_or_else(|| TemplateError::UnknownField(name.to_owned()))
+}
+
+/// One list item per ticket, e.g.
+/// `- blocks [AUTO-7](https://example.atlassian.net/browse/AUTO-7) Fix logout (To Do)`.
+fn render_linked_tickets<'a>(
+    linked_tickets: impl IntoIterator<Item = &'a LinkedTicket>,
+    jira_host: &str,
+) -> String {
+    linked_tickets
+        .into_iter()
+        .map(|linked_ticket| {
+            let issue_type = match &linked_ticket.issue_type {
+                Some(issue_type) if linked_ticket.is_epic() => format!("{issue_type} "),
+                _ => String::new(),
+            };
+            let progress = linked_ticket
+                .child_progress
+                .map_or(String::new(), |progress| {
+                    format!(", {}", describe_progress(progress))
+                });
+            format!(
+                "- {} {issue_type}[{}]({}) {} ({}{progress})\n",
+                linked_ticket.relation,
+                linked_ticket.key,
+                browse_url(jira_host, &linked_ticket.key),
+                linked_ticket.title,
+                linked_ticket.status,
+            )
+        })
+        .collect()
+}
+
+fn custom_field_markdown(custom_field: &CustomFieldValue) -> String {
+    match custom_field.field_type {
+        CustomFieldType::Markup => transpile_atlassian_markup_to_markdown(&custom_field.value),
+        _ => custom_field.value.to_owned(),
+    }
+}
+
+fn describe_progress(progress: ChildProgress) -> String {
+    format!("{}/{} done", progress.done, progress.total)
+}
+
+/// Each comment as its author and age in bold, followed by the body as Markdown.
+fn render_comments(comments: &[TicketComment]) -> String {
+    comments
+        .iter()
+        .map(|comment| {
+            let age = comment
+                .created
+                .and_then(|created| created.elapsed().ok())
+                .map_or(String::new(), |age| format!(", {} ago", describe_age(age)));
+            format!(
+                "**{}{age}**\n\n{}",
+                comment.author,
+                transpile_atlassian_markup_to_markdown(&comment.body)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn render_parts(parts: &[Part], context: &TemplateContext, rendered: &mut String) {
+    for part in parts {
+        match part {
+            Part::Text(text) => rendered.push_str(text),
+            Part::Field { field, fallback } => {
+                let value = field.value(context);
+                match fallback {
+                    Some(fallback) if value.is_empty() => rendered.push_str(fallback),
+                    _ => rendered.push_str(&value),
+                }
+            }
+            Part::Icon(icon) => rendered.push_str(context.icon_set.icon(*icon)),
+            Part::Conditional {
+                field,
+                shown_when_empty,
+                parts,
+            } => {
+                if field.value(context).is_empty() == *shown_when_empty {
+                    render_parts(parts, context, rendered);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn render(template: &str, ticket: &JiraTicket, stale_for: Option<Duration>) -> String {
+        TicketTemplate::parse(template)
+            .unwrap()
+            .render(&TemplateContext {
+                ticket,
+                jira_host: "https://example.atlassian.net",
+                stale_for,
+                icon_set: IconSet::Ascii,
+            })
+    }
+
+    fn ticket() -> JiraTicket {
+        JiraTicket {
+            labels: vec!["auth".to_owned(), "web".to_owned()],
+            ..JiraTicket::test_ticket("AUTO-12").with_status("In Progress", "indeterminate")
+        }
+    }
+
+    #[test]
+    fn fields_are_filled_in_with_fallbacks_for_empty_ones() {
+        assert_eq!(
+            render(
+                "{key}: {title} {icon:status} {status} | {assignee|Unassigned} | {labels}",
+                &ticket(),
+                None
+            ),
+            "AUTO-12: Fix login bug [*] In Progress | Unassigned | auth, web"
+        );
+    }
+
+    #[test]
+    fn conditionals_depend_on_whether_the_field_is_empty() {
+        let template = "{title}{?due} due {due}{/due}{!assignee} nobody{/assignee}{?stale}, {stale} old{/stale}";
+
+        assert_eq!(
+            render(template, &ticket(), Some(Duration::from_secs(2 * 60 * 60))),
+            "Fix login bug nobody, 2 hours old"
+        );
+        let ticket = JiraTicket {
+            due: Some("2025-01-31".to_owned()),
+            assignee: Some("Ada Lovelace".to_owned()),
+            ..ticket()
+        };
+        assert_eq!(
+            render(template, &ticket, None),
+            "Fix login bug due 2025-01-31"
+        );
+    }
+
+    #[test]
+    fn comments_show_their_author_and_age() {
+        let commented_ticket = JiraTicket {
+            comments: Some(vec![TicketComment {
+                author: "Ada Lovelace".to_owned(),
+                created: Some(SystemTime::now() - Duration::from_secs(2 * 60 * 60)),
+                body: "Looking into it".to_owned(),
+            }]),
+            ..ticket()
+        };
+
+        assert_eq!(
+            render("{?comments}{comments}{/comments}", &commented_ticket, None),
+            "**Ada Lovelace, 2 hours ago**\n\nLooking into it\n"
+        );
+        assert_eq!(
+            render("{!comments}none{/comments}", &ticket(), None),
+            "none"
+        );
+    }
+
+    #[test]
+    fn custom_fields_are_found_by_name() {
+        let custom_field = |name: &str, field_type, value: &str| CustomFieldValue {
+            name: name.to_owned(),
+            field_type,
+            value: value.to_owned(),
+        };
+        let estimated_ticket = JiraTicket {
+            custom_fields: vec![
+                custom_field("Story points", CustomFieldType::Number, "5"),
+                custom_field("Acceptance criteria", CustomFieldType::Markup, "Logs in"),
+            ],
+            ..ticket()
+        };
+
+        assert_eq!(
+            render(
+                "{custom:Story points} points{?custom:Team}, {custom:Team}{/custom:Team}",
+                &estimated_ticket,
+                None
+            ),
+            "5 points"
+        );
+        assert_eq!(
+            render("{sections}", &estimated_ticket, None),
+            "### Acceptance criteria\n\nLogs in\n"
+        );
+    }
+
+    #[test]
+    fn related_tickets_are_listed_as_links() {
+        let linked_ticket = |relation: &str, key: &str, issue_type: &str| LinkedTicket {
+            relation: relation.to_owned(),
+            key: key.to_owned(),
+            title: format!("Summary of {key}"),
+            status: "In Progress".to_owned(),
+            status_category: "indeterminate".to_owned(),
+            issue_type: Some(issue_type.to_owned()),
+            child_progress: None,
+        };
+        let ticket = JiraTicket {
+            parent: Some(LinkedTicket {
+                child_progress: Some(ChildProgress { done: 3, total: 5 }),
+                ..linked_ticket("parent", "AUTO-1", "Epic")
+            }),
+            links: vec![linked_ticket("blocks", "AUTO-20", "Task")],
+            ..ticket()
+        };
+
+        assert_eq!(
+            render("{related}", &ticket, None),
+            "- parent Epic [AUTO-1](https://example.atlassian.net/browse/AUTO-1) Summary of AUTO-1 (In Progress, 3/5 done)\n\
+             - blocks [AUTO-20](https://example.atlassian.net/browse/AUTO-20) Summary of AUTO-20 (In Progress)\n"
+        );
+        assert_eq!(
+            render("{!subtasks}no subtasks{/subtasks}", &ticket, None),
+            "no subtasks"
+        );
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(render("{{{key}}}", &ticket(), None), "{AUTO-12}");
+    }
+
+    #[test]
+    fn mistakes_are_rejected() {
+        assert_eq!(
+            TicketTemplate::parse("{titel}").unwrap_err(),
+            TemplateError::UnknownField("titel".to_owned())
+        );
+        assert_eq!(
+            TicketTemplate::parse("{icon:bug}").unwrap_err(),
+            TemplateError::UnknownIcon("bug".to_owned())
+        );
+        assert_eq!(
+            TicketTemplate::parse("{?due}{?stale}{/due}{/stale}").unwrap_err(),
+            TemplateError::UnexpectedClose("due".to_owned())
+        );
+        assert_eq!(
+            TicketTemplate::parse("{?due} due {due}").unwrap_err(),
+            TemplateError::UnclosedConditional("due".to_owned())
+        );
+        assert_eq!(
+            TicketTemplate::parse("{title").unwrap_err(),
+            TemplateError::UnclosedPlaceholder
+        );
+        assert_eq!(
+            TicketTemplate::parse("title}").unwrap_err(),
+            TemplateError::UnopenedPlaceholder
+        );
+    }
+}
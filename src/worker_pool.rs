@@ -0,0 +1,76 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+
+use crossbeam_channel::Sender;
+use log::{error, info};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of threads that requests are handed to, so that waiting on Jira for
+/// one request never holds up the message loop or the other requests.
+pub struct WorkerPool {
+    job_sender: Sender<Job>,
+}
+
+impl WorkerPool {
+    pub fn new(worker_count: usize) -> WorkerPool {
+        let (job_sender, job_receiver) = crossbeam_channel::unbounded::<Job>();
+        for worker in 0..worker_count {
+            let job_receiver = job_receiver.clone();
+            thread::Builder::new()
+                .name(format!("worker-{worker}"))
+                .spawn(move || {
+                    // Runs until the pool, and with it the sending half, is dropped.
+                    for job in job_receiver {
+                        // A job failing must not leave the pool a worker short.
+                        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                            error!("A job on worker {worker} panicked");
+                        }
+                    }
+                    info!("Worker {worker} stopped");
+                })
+                .unwrap();
+        }
+        WorkerPool { job_sender }
+    }
+
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        self.job_sender.send(Box::new(job)).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Barrier};
+
+    #[test]
+    fn runs_jobs_at_the_same_time() {
+        let worker_pool = WorkerPool::new(2);
+        // Neither job can finish unless the other is running alongside it.
+        let barrier = Arc::new(Barrier::new(2));
+        let (result_sender, result_receiver) = crossbeam_channel::unbounded();
+        for job in 0..2 {
+            let barrier = Arc::clone(&barrier);
+            let result_sender = result_sender.clone();
+            worker_pool.execute(move || {
+                barrier.wait();
+                result_sender.send(job).unwrap();
+            });
+        }
+
+        let mut results: Vec<i32> = result_receiver.iter().take(2).collect();
+        results.sort();
+        assert_eq!(results, vec![0, 1]);
+    }
+
+    #[test]
+    fn workers_outlive_jobs_that_panic() {
+        let worker_pool = WorkerPool::new(1);
+        let (result_sender, result_receiver) = crossbeam_channel::unbounded();
+        worker_pool.execute(|| panic!("Job failed"));
+        worker_pool.execute(move || result_sender.send(()).unwrap());
+
+        assert!(result_receiver.recv().is_ok());
+    }
+}
@@ -0,0 +1,190 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use ignore::WalkBuilder;
+use log::{info, warn};
+use lsp_types::{InitializeParams, Uri};
+
+/// Files bigger than this are assumed to be generated or binary and are not scanned.
+const MAX_SCANNED_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Finds the text files in the workspace folders, skipping whatever `.gitignore`,
+/// `.ignore` and hidden file rules exclude.
+pub struct WorkspaceScanner {
+    folders: Vec<PathBuf>,
+}
+
+impl WorkspaceScanner {
+    pub fn new(initialize_params: &InitializeParams) -> WorkspaceScanner {
+        #[allow(deprecated)]
+        let folder_uris: Vec<&Uri> = match &initialize_params.workspace_folders {
+            Some(workspace_folders) => workspace_folders.iter().map(|x| &x.uri).collect(),
+            None => initialize_params.root_uri.iter().collect(),
+        };
+        WorkspaceScanner {
+            folders: folder_uris
+                .into_iter()
+                .filter_map(path_from_file_uri)
+                .collect(),
+        }
+    }
+
+    /// Lists every file in the workspace worth scanning. They are read separately with
+    /// `read_file`, so that progress through them can be reported.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.folders
+            .iter()
+            .flat_map(|folder| {
+                info!("Scanning workspace folder {}", folder.display());
+                scanned_files(&WalkBuilder::new(folder))
+            })
+            .collect()
+    }
+
+    /// Reads a single workspace file back from disk, e.g. once the client stops editing it.
+    /// Files `files` skips are not read either.
+    pub fn read(&self, uri: &Uri) -> Option<String> {
+        let path = path_from_file_uri(uri)?;
+        if !self.is_scanned(&path) {
+            return None;
+        }
+        fs::read_to_string(path).ok()
+    }
+
+    /// Walks only the folders leading to the file, which still applies the ignore files
+    /// found along the way.
+    fn is_scanned(&self, path: &Path) -> bool {
+        self.folders
+            .iter()
+            .filter(|folder| path.starts_with(folder))
+            .any(|folder| {
+                let file = path.to_owned();
+                let mut walk_builder = WalkBuilder::new(folder);
+                walk_builder.filter_entry(move |entry| file.starts_with(entry.path()));
+                scanned_files(&walk_builder).any(|scanned_file| scanned_file == path)
+            })
+    }
+}
+
+fn scanned_files(walk_builder: &WalkBuilder) -> impl Iterator<Item = PathBuf> {
+    walk_builder
+        .build()
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!("Skipping part of the workspace because {e}");
+                None
+            }
+        })
+        .filter(|entry| {
+            entry
+                .metadata()
+                .is_ok_and(|metadata| metadata.is_file() && metadata.len() <= MAX_SCANNED_FILE_SIZE)
+        })
+        .map(|entry| entry.into_path())
+}
+
+/// Reads a workspace file, yielding its uri and contents.
+pub fn read_file(path: &Path) -> Option<(Uri, String)> {
+    // Files that are not UTF-8 text are not worth scanning.
+    let contents = fs::read_to_string(path).ok()?;
+    Some((file_uri_from_path(path)?, contents))
+}
+
+pub fn path_from_file_uri(uri: &Uri) -> Option<PathBuf> {
+    if uri.scheme().map(|scheme| scheme.as_str()) != Some("file") {
+        return None;
+    }
+    let path = uri.path().as_estr().decode().into_string().ok()?;
+    Some(PathBuf::from(path.as_ref()))
+}
+
+/// Percent-encodes everything in the path but unreserved characters and separators,
+/// which is how editors write `file://` uris.
+pub fn file_uri_from_path(path: &Path) -> Option<Uri> {
+    let path = path.to_str()?;
+    let mut uri = String::with_capacity(path.len() + 7);
+    uri.push_str("file://");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    Uri::from_str(&uri).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_uris_round_trip() {
+        let path = Path::new("/home/me/my project/notes ü.md");
+
+        let uri = file_uri_from_path(path).unwrap();
+
+        assert_eq!(
+            uri.as_str(),
+            "file:///home/me/my%20project/notes%20%C3%BC.md"
+        );
+        assert_eq!(path_from_file_uri(&uri).unwrap(), path);
+    }
+
+    #[test]
+    fn scan_skips_ignored_files() {
+        let folder =
+            std::env::temp_dir().join(format!("refrences-lsp-scan-{}", std::process::id()));
+        fs::create_dir_all(folder.join("target")).unwrap();
+        // The ignore crate only honours `.gitignore` inside git repositories.
+        fs::create_dir_all(folder.join(".git")).unwrap();
+        fs::write(folder.join(".gitignore"), "target/\n").unwrap();
+        fs::write(folder.join("notes.md"), "AUTO-1").unwrap();
+        fs::write(folder.join("target").join("build.log"), "AUTO-2").unwrap();
+
+        let scanner = WorkspaceScanner {
+            folders: vec![folder.to_owned()],
+        };
+        let scanned: Vec<String> = scanner
+            .files()
+            .iter()
+            .filter_map(|path| read_file(path))
+            .map(|(_, contents)| contents)
+            .collect();
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(scanned, vec!["AUTO-1"]);
+    }
+
+    #[test]
+    fn closed_files_are_only_read_when_scanned() {
+        let folder =
+            std::env::temp_dir().join(format!("refrences-lsp-read-{}", std::process::id()));
+        fs::create_dir_all(folder.join("target")).unwrap();
+        fs::create_dir_all(folder.join(".git")).unwrap();
+        fs::write(folder.join(".gitignore"), "target/\n").unwrap();
+        fs::write(folder.join("notes.md"), "AUTO-1").unwrap();
+        fs::write(folder.join("target").join("build.log"), "AUTO-2").unwrap();
+        fs::write(
+            folder.join("generated.md"),
+            "AUTO-3 ".repeat(MAX_SCANNED_FILE_SIZE as usize),
+        )
+        .unwrap();
+
+        let scanner = WorkspaceScanner {
+            folders: vec![folder.to_owned()],
+        };
+        let read = |path: PathBuf| scanner.read(&file_uri_from_path(&path).unwrap());
+        let notes = read(folder.join("notes.md"));
+        let build_log = read(folder.join("target").join("build.log"));
+        let generated = read(folder.join("generated.md"));
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(notes.as_deref(), Some("AUTO-1"));
+        assert_eq!(build_log, None);
+        assert_eq!(generated, None);
+    }
+}